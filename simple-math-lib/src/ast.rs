@@ -0,0 +1,654 @@
+use bigdecimal::BigDecimal;
+use calculator::CalcError;
+use parser::{Span, Token};
+use std::fmt;
+use std::iter::Peekable;
+
+/// An operator taking two operands
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BinOp {
+    Add,
+    And,
+    BitshiftLeft,
+    BitshiftRight,
+    Div,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    LogicalAnd,
+    LogicalOr,
+    Mul,
+    NotEqual,
+    Or,
+    Pow,
+    Rem,
+    Sub,
+    Xor
+}
+impl BinOp {
+    /// How tightly the operator binds, which is the level of the parser function reading it
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::LogicalOr => 2,
+            BinOp::LogicalAnd => 3,
+            BinOp::Equal | BinOp::NotEqual |
+            BinOp::Less | BinOp::LessEqual |
+            BinOp::Greater | BinOp::GreaterEqual => 4,
+            BinOp::Xor => 5,
+            BinOp::Or => 6,
+            BinOp::And => 7,
+            BinOp::BitshiftLeft | BinOp::BitshiftRight => 8,
+            BinOp::Add | BinOp::Sub => 9,
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 10,
+            BinOp::Pow => 11
+        }
+    }
+}
+impl fmt::Display for BinOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BinOp::Add => "+",
+            BinOp::And => "&",
+            BinOp::BitshiftLeft => "<<",
+            BinOp::BitshiftRight => ">>",
+            BinOp::Div => "/",
+            BinOp::Equal => "==",
+            BinOp::Greater => ">",
+            BinOp::GreaterEqual => ">=",
+            BinOp::Less => "<",
+            BinOp::LessEqual => "<=",
+            BinOp::LogicalAnd => "&&",
+            BinOp::LogicalOr => "||",
+            BinOp::Mul => "*",
+            BinOp::NotEqual => "!=",
+            BinOp::Or => "|",
+            BinOp::Pow => "**",
+            BinOp::Rem => "%",
+            BinOp::Sub => "-",
+            BinOp::Xor => "^"
+        })
+    }
+}
+
+/// An operator taking one operand
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnOp {
+    DoubleFactorial,
+    Factorial,
+    Neg,
+    Not
+}
+
+/// A node in the expression tree, along with the part of the input it was parsed from
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Expr {
+    pub span: Span,
+    pub kind: ExprKind
+}
+impl Expr {
+    pub fn new(span: Span, kind: ExprKind) -> Self {
+        Expr { span, kind }
+    }
+    /// Checks if calculating this can change anything outside of its own function call,
+    /// by setting a global variable or defining a function
+    pub fn has_side_effects(&self) -> bool {
+        match self.kind {
+            ExprKind::Num(_) |
+            ExprKind::Var(_) => false,
+            ExprKind::AssignGlobal(..) |
+            ExprKind::Define(..) => true,
+            ExprKind::Unary(_, ref expr) |
+            ExprKind::Assign(_, ref expr) => expr.has_side_effects(),
+            ExprKind::Binary(ref left, _, ref right) => left.has_side_effects() || right.has_side_effects(),
+            ExprKind::Chain(ref first, ref rest) => {
+                first.has_side_effects() || rest.iter().any(|(_, expr)| expr.has_side_effects())
+            },
+            ExprKind::Conditional(ref cond, ref then, ref otherwise) => {
+                cond.has_side_effects() || then.has_side_effects() || otherwise.has_side_effects()
+            },
+            ExprKind::Call(_, ref exprs) |
+            ExprKind::Sequence(ref exprs) => exprs.iter().any(Expr::has_side_effects)
+        }
+    }
+}
+
+/// The different kinds of nodes in the expression tree
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExprKind {
+    /// A number literal
+    Num(BigDecimal),
+    /// A variable lookup
+    Var(String),
+    /// A unary operation
+    Unary(UnOp, Box<Expr>),
+    /// A binary operation with a right associative operator
+    Binary(Box<Expr>, BinOp, Box<Expr>),
+    /// Left associative operators of the same precedence, like `1 + 2 - 3`, calculated from left
+    /// to right. Keeping them in a list instead of nesting them keeps long chains from recursing.
+    Chain(Box<Expr>, Vec<(BinOp, Expr)>),
+    /// A conditional, where only the branch that's taken is calculated
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// A call to a builtin or user-defined function
+    Call(String, Vec<Expr>),
+    /// A variable assignment, local to the function call it's in
+    Assign(String, Box<Expr>),
+    /// A variable assignment that always sets the global variable
+    AssignGlobal(String, Box<Expr>),
+    /// Statements calculated in order, where the last one is the result
+    Sequence(Vec<Expr>),
+    /// A function definition, with the names of its parameters if it has a list of them
+    Define(String, Option<Vec<String>>, Box<Expr>)
+}
+
+/// Writes the expression back as source code that parses to the same tree,
+/// with only the parentheses it needs
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expr(f, self, 0)
+    }
+}
+/// Writes `expr`, in parentheses if it binds looser than `min`
+fn write_expr(f: &mut fmt::Formatter, expr: &Expr, min: u8) -> fmt::Result {
+    let precedence = match expr.kind {
+        ExprKind::Num(_) | ExprKind::Var(_) | ExprKind::Call(..) => 15,
+        ExprKind::Unary(UnOp::Neg, _) => 14,
+        ExprKind::Unary(UnOp::Not, _) => 13,
+        ExprKind::Unary(_, _) => 12,
+        ExprKind::Binary(_, op, _) => op.precedence(),
+        ExprKind::Chain(_, ref rest) => rest[0].0.precedence(),
+        ExprKind::Conditional(..) |
+        ExprKind::Assign(..) |
+        ExprKind::AssignGlobal(..) |
+        ExprKind::Define(..) => 1,
+        ExprKind::Sequence(_) => 0
+    };
+    if precedence < min {
+        write!(f, "(")?;
+        write_expr(f, expr, 0)?;
+        return write!(f, ")");
+    }
+
+    match expr.kind {
+        ExprKind::Num(ref num) => write!(f, "{}", num),
+        ExprKind::Var(ref name) => write!(f, "{}", name),
+        ExprKind::Unary(op, ref expr) => match op {
+            UnOp::DoubleFactorial => { write_expr(f, expr, 13)?; write!(f, "!!") },
+            UnOp::Factorial => { write_expr(f, expr, 13)?; write!(f, "!") },
+            UnOp::Neg => { write!(f, "-")?; write_expr(f, expr, 14) },
+            UnOp::Not => { write!(f, "~")?; write_expr(f, expr, 13) }
+        },
+        ExprKind::Binary(ref expr1, op, ref expr2) => {
+            write_expr(f, expr1, precedence + 1)?;
+            write!(f, " {} ", op)?;
+            write_expr(f, expr2, precedence)
+        },
+        ExprKind::Chain(ref first, ref rest) => {
+            write_expr(f, first, precedence)?;
+            for &(op, ref expr) in rest {
+                write!(f, " {} ", op)?;
+                write_expr(f, expr, precedence + 1)?;
+            }
+            Ok(())
+        },
+        ExprKind::Conditional(ref cond, ref then, ref otherwise) => {
+            write_expr(f, cond, 2)?;
+            write!(f, " ? ")?;
+            write_expr(f, then, 1)?;
+            write!(f, " : ")?;
+            write_expr(f, otherwise, 1)
+        },
+        ExprKind::Call(ref name, ref args) => {
+            write!(f, "{}(", name)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i != 0 {
+                    write!(f, ", ")?;
+                }
+                write_expr(f, arg, 1)?;
+            }
+            write!(f, ")")
+        },
+        ExprKind::Assign(ref name, ref expr) => {
+            write!(f, "{} = ", name)?;
+            write_expr(f, expr, 1)
+        },
+        ExprKind::AssignGlobal(ref name, ref expr) => {
+            write!(f, "global {} = ", name)?;
+            write_expr(f, expr, 1)
+        },
+        ExprKind::Sequence(ref exprs) => {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i != 0 {
+                    write!(f, "; ")?;
+                }
+                write_expr(f, expr, 1)?;
+            }
+            Ok(())
+        },
+        ExprKind::Define(ref name, None, ref body) => {
+            write!(f, "{} = (", name)?;
+            write_expr(f, body, 0)?;
+            write!(f, ")")
+        },
+        ExprKind::Define(ref name, Some(ref params), ref body) => {
+            write!(f, "{}({}) = ", name, params.join(", "))?;
+            write_expr(f, body, 1)
+        }
+    }
+}
+
+struct Parser<I: Iterator<Item = (Span, Token)>> {
+    end: Span,
+    level: u8,
+    tokens: Peekable<I>
+}
+impl<I: Iterator<Item = (Span, Token)>> Parser<I> {
+    fn peek(&mut self) -> Option<&Token> {
+        self.tokens.peek().map(|(_, token)| token)
+    }
+    fn next(&mut self) -> Option<(Span, Token)> {
+        let next = self.tokens.next();
+        if let Some((span, _)) = next {
+            self.end = Span::new(span.end, span.end);
+        }
+        next
+    }
+    /// Consumes the next token and returns its span, or an empty span
+    /// at the end of the input if there are no tokens left
+    fn skip(&mut self) -> Span {
+        match self.next() {
+            Some((span, _)) => span,
+            None => self.end
+        }
+    }
+    /// Runs `parse` one level deeper. Everything that nests, like parentheses or a chain of
+    /// right associative operators, is refused past a limit, since calculating it recurses.
+    fn nested<F>(&mut self, span: Span, parse: F) -> Result<Expr, CalcError>
+        where F: FnOnce(&mut Self) -> Result<Expr, CalcError>
+    {
+        if self.level == u8::MAX {
+            return Err(CalcError::TooDeep.at(span));
+        }
+        self.level += 1;
+        let expr = parse(self);
+        self.level -= 1;
+        expr
+    }
+}
+
+/// Parses the tokens gotten by the parser into an expression tree
+pub fn parse<I: Iterator<Item = (Span, Token)>>(tokens: I) -> Result<Expr, CalcError> {
+    let mut parser = Parser {
+        end: Span::default(),
+        level: 0,
+        tokens: tokens.peekable()
+    };
+
+    let expr = parse_sequence(&mut parser)?;
+
+    match parser.next() {
+        Some((span, token)) => Err(CalcError::ExpectedEOF(token).at(span)),
+        None => Ok(expr)
+    }
+}
+
+fn binary(expr1: Expr, op: BinOp, expr2: Expr) -> Expr {
+    Expr::new(expr1.span.to(expr2.span), ExprKind::Binary(Box::new(expr1), op, Box::new(expr2)))
+}
+fn chain(first: Expr, rest: Vec<(BinOp, Expr)>) -> Expr {
+    let span = match rest.last() {
+        Some((_, last)) => first.span.to(last.span),
+        None => return first
+    };
+    Expr::new(span, ExprKind::Chain(Box::new(first), rest))
+}
+fn unary(span: Span, op: UnOp, expr: Expr) -> Expr {
+    Expr::new(span, ExprKind::Unary(op, Box::new(expr)))
+}
+
+/// Parses statements separated by semicolons
+fn parse_sequence<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let mut exprs = vec![parse_level1(parser)?];
+
+    while let Some(&Token::Semicolon) = parser.peek() {
+        parser.next();
+        // Allow a semicolon after the last statement
+        match parser.peek() {
+            Some(&Token::ParenClose) |
+            Some(&Token::Separator) |
+            None => break,
+            Some(_) => exprs.push(parse_level1(parser)?)
+        }
+    }
+
+    if exprs.len() == 1 {
+        return Ok(exprs.remove(0));
+    }
+    let span = exprs[0].span.to(exprs[exprs.len() - 1].span);
+    Ok(Expr::new(span, ExprKind::Sequence(exprs)))
+}
+fn parse_level1<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let cond = parse_level2(parser)?;
+
+    if let Some(&Token::Then) = parser.peek() {
+        let then_span = parser.skip();
+        let then = parser.nested(then_span, parse_level1)?;
+        let else_span = match parser.peek() {
+            Some(&Token::Else) => parser.skip(),
+            _ => return Err(CalcError::ExpectedElse.at(then_span))
+        };
+        let otherwise = parser.nested(else_span, parse_level1)?; // Right associative
+
+        let span = cond.span.to(otherwise.span);
+        return Ok(Expr::new(span, ExprKind::Conditional(Box::new(cond), Box::new(then), Box::new(otherwise))));
+    }
+
+    Ok(cond)
+}
+fn parse_level2<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level3(parser)?;
+    let mut rest = Vec::new();
+
+    while let Some(&Token::LogicalOr) = parser.peek() {
+        parser.next();
+        rest.push((BinOp::LogicalOr, parse_level3(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level3<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level4(parser)?;
+    let mut rest = Vec::new();
+
+    while let Some(&Token::LogicalAnd) = parser.peek() {
+        parser.next();
+        rest.push((BinOp::LogicalAnd, parse_level4(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level4<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level5(parser)?;
+    let mut rest = Vec::new();
+
+    loop {
+        let op = match parser.peek() {
+            Some(&Token::Equal) => BinOp::Equal,
+            Some(&Token::NotEqual) => BinOp::NotEqual,
+            Some(&Token::Less) => BinOp::Less,
+            Some(&Token::LessEqual) => BinOp::LessEqual,
+            Some(&Token::Greater) => BinOp::Greater,
+            Some(&Token::GreaterEqual) => BinOp::GreaterEqual,
+            _ => break
+        };
+        parser.next();
+        rest.push((op, parse_level5(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level5<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level6(parser)?;
+
+    if let Some(&Token::Xor) = parser.peek() {
+        let span = parser.skip();
+        let expr2 = parser.nested(span, parse_level5)?;
+
+        return Ok(binary(expr1, BinOp::Xor, expr2));
+    }
+
+    Ok(expr1)
+}
+fn parse_level6<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level7(parser)?;
+
+    if let Some(&Token::Or) = parser.peek() {
+        let span = parser.skip();
+        let expr2 = parser.nested(span, parse_level6)?;
+
+        return Ok(binary(expr1, BinOp::Or, expr2));
+    }
+
+    Ok(expr1)
+}
+fn parse_level7<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level8(parser)?;
+
+    if let Some(&Token::And) = parser.peek() {
+        let span = parser.skip();
+        let expr2 = parser.nested(span, parse_level7)?;
+
+        return Ok(binary(expr1, BinOp::And, expr2));
+    }
+
+    Ok(expr1)
+}
+fn parse_level8<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level9(parser)?;
+    let mut rest = Vec::new();
+
+    loop {
+        let op = match parser.peek() {
+            Some(&Token::BitshiftLeft) => BinOp::BitshiftLeft,
+            Some(&Token::BitshiftRight) => BinOp::BitshiftRight,
+            _ => break
+        };
+        parser.next();
+        rest.push((op, parse_level9(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level9<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level10(parser)?;
+    let mut rest = Vec::new();
+
+    loop {
+        let op = match parser.peek() {
+            Some(&Token::Add) => BinOp::Add,
+            Some(&Token::Sub) => BinOp::Sub,
+            _ => break
+        };
+        parser.next();
+        rest.push((op, parse_level10(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level10<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level11(parser)?;
+    let mut rest = Vec::new();
+
+    loop {
+        let op = match parser.peek() {
+            Some(&Token::Mul) => BinOp::Mul,
+            Some(&Token::Div) => BinOp::Div,
+            Some(&Token::Rem) => BinOp::Rem,
+            _ => break
+        };
+        parser.next();
+        rest.push((op, parse_level11(parser)?));
+    }
+
+    Ok(chain(expr1, rest))
+}
+fn parse_level11<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr1 = parse_level12(parser)?;
+    if let Some(&Token::Pow) = parser.peek() {
+        let span = parser.skip();
+        let expr2 = parser.nested(span, parse_level11)?; // Right associative
+
+        return Ok(binary(expr1, BinOp::Pow, expr2));
+    }
+    Ok(expr1)
+}
+fn parse_level12<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    let expr = parse_level13(parser)?;
+    if let Some(&Token::Factorial) = parser.peek() {
+        let span = expr.span.to(parser.skip());
+        if let Some(&Token::Factorial) = parser.peek() {
+            let span = span.to(parser.skip());
+            return Ok(unary(span, UnOp::DoubleFactorial, expr));
+        }
+        return Ok(unary(span, UnOp::Factorial, expr));
+    }
+    Ok(expr)
+}
+fn parse_level13<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    if let Some(&Token::Not) = parser.peek() {
+        let start = parser.skip();
+        let expr = parser.nested(start, parse_level13)?;
+
+        return Ok(unary(start.to(expr.span), UnOp::Not, expr));
+    }
+
+    parse_paren(parser, None)
+}
+/// Parses the contents of a pair of parentheses, stopping at the closing
+/// parenthesis or a separator
+fn parse_inner<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>, open: Span) -> Result<Expr, CalcError> {
+    let expr = parser.nested(open, parse_sequence)?;
+
+    match parser.peek() {
+        Some(&Token::ParenClose) |
+        Some(&Token::Separator) |
+        None => Ok(expr),
+
+        Some(_) => {
+            let (span, token) = parser.next().unwrap();
+            Err(CalcError::ExpectedEOF(token).at(span))
+        }
+    }
+}
+fn parse_paren<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>, name: Option<(Span, String)>) -> Result<Expr, CalcError> {
+    if let Some(&Token::ParenOpen) = parser.peek() {
+        let open = parser.skip();
+
+        let mut args = Vec::new();
+
+        if let Some(&Token::ParenClose) = parser.peek() {
+        } else {
+            args.push(parse_inner(parser, open)?);
+
+            while let Some(&Token::Separator) = parser.peek() {
+                parser.next();
+                args.push(parse_inner(parser, open)?);
+            }
+        }
+        let close = match parser.next() {
+            Some((span, Token::ParenClose)) => span,
+            _ => return Err(CalcError::UnclosedParen.at(open))
+        };
+
+        if let Some((start, name)) = name {
+            if let Some(&Token::Assign) = parser.peek() {
+                let assign = parser.skip();
+                let mut params = Vec::with_capacity(args.len());
+                for arg in args {
+                    match arg.kind {
+                        ExprKind::Var(ref param) if params.contains(param) => {
+                            return Err(CalcError::DuplicateParameter(param.clone()).at(arg.span));
+                        },
+                        ExprKind::Var(param) => params.push(param),
+                        _ => return Err(CalcError::InvalidParameter.at(arg.span))
+                    }
+                }
+                let body = parser.nested(assign, parse_level1)?;
+                return Ok(Expr::new(start.to(body.span), ExprKind::Define(name, Some(params), Box::new(body))));
+            }
+            return Ok(Expr::new(start.to(close), ExprKind::Call(name, args)));
+        }
+        if args.len() != 1 {
+            return Err(CalcError::IncorrectArguments(1, args.len()).at(open.to(close)));
+        }
+        return Ok(Expr::new(open.to(close), args.remove(0).kind));
+    } else if name.is_none() {
+        if let Some(&Token::BlockName(_)) = parser.peek() {
+            // Really ugly code, but we need to know the type *before* we walk out on it
+            if let Some((span, Token::BlockName(name))) = parser.next() {
+                return parse_paren(parser, Some((span, name)));
+            }
+        }
+    }
+
+    parse_primary(parser)
+}
+fn parse_primary<I: Iterator<Item = (Span, Token)>>(parser: &mut Parser<I>) -> Result<Expr, CalcError> {
+    match parser.next() {
+        Some((span, Token::Num(num))) => Ok(Expr::new(span, ExprKind::Num(num))),
+        Some((span, Token::Sub)) => {
+            let expr = parser.nested(span, |parser| parse_paren(parser, None))?;
+            Ok(unary(span.to(expr.span), UnOp::Neg, expr))
+        },
+        Some((span, Token::VarAssign(name))) => {
+            if let Some(&Token::ParenOpen) = parser.peek() {
+                let open = parser.skip();
+
+                let body = parse_inner(parser, open)?;
+                let close = match parser.next() {
+                    Some((close, Token::ParenClose)) => close,
+                    Some((sep, Token::Separator)) => return Err(CalcError::SeparatorInDef.at(sep)),
+                    _ => return Err(CalcError::UnclosedParen.at(open))
+                };
+
+                Ok(Expr::new(span.to(close), ExprKind::Define(name, None, Box::new(body))))
+            } else {
+                let expr = parser.nested(span, parse_level1)?;
+                Ok(Expr::new(span.to(expr.span), ExprKind::Assign(name, Box::new(expr))))
+            }
+        },
+        Some((span, Token::Global)) => {
+            match parser.next() {
+                Some((_, Token::VarAssign(name))) => {
+                    let expr = parser.nested(span, parse_level1)?;
+                    Ok(Expr::new(span.to(expr.span), ExprKind::AssignGlobal(name, Box::new(expr))))
+                },
+                _ => Err(CalcError::ExpectedAssignment.at(span))
+            }
+        },
+        Some((span, Token::VarGet(name))) => Ok(Expr::new(span, ExprKind::Var(name))),
+        Some((span, _)) => Err(CalcError::InvalidSyntax.at(span)),
+        None => Err(CalcError::InvalidSyntax.at(parser.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser;
+
+    fn print(input: &str) -> String {
+        parse(parser::parse(input).unwrap().into_iter()).unwrap().to_string()
+    }
+
+    #[test]
+    fn printer_round_trip() {
+        let inputs = [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "1 - (2 - 3)",
+            "(1 - 2) - 3",
+            "2 ** 3 ** 2",
+            "(2 ** 3) ** 2",
+            "-x ** 2",
+            "(-x) ** 2",
+            "~5!",
+            "x = y = 3",
+            "a ? b : c ? d : e",
+            "(a ? b : c) ? d : e",
+            "f(x, y) = x * y; f(1 + 2, 3)",
+            "global x = 1 << 2 | 3",
+            "n!! + 3!"
+        ];
+        for input in inputs.iter() {
+            let printed = print(input);
+            assert_eq!(print(&printed), printed, "{} printed as {}", input, printed);
+        }
+        assert_eq!(print("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(print("((1 - 2)) - 3"), "1 - 2 - 3");
+        assert_eq!(print("1 - (2 - 3)"), "1 - (2 - 3)");
+        assert_eq!(print("(2 ** 3) ** 2"), "(2 ** 3) ** 2");
+        assert_eq!(print("2 ** (3 ** 2)"), "2 ** 3 ** 2");
+    }
+}
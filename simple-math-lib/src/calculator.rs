@@ -1,19 +1,38 @@
+use ast::{BinOp, Expr, ExprKind, UnOp};
 use bigdecimal::BigDecimal;
-use num::bigint::Sign;
-use parser::{Token, ParseError};
+use integer;
+use math;
+use parser::{ParseError, Span, Token};
+use num::bigint::{Sign, ToBigInt};
+use num::{BigInt, BigRational, Integer, One, Signed, ToPrimitive, Zero};
+use number::{self, Mode, Number, Precision, Rounding, Width};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::iter::Peekable;
-use std::{self, mem};
+use std;
 
 /// An error when calculating
 #[derive(Debug, Fail)]
 pub enum CalcError {
+    #[fail(display = "Cannot assign to the constant \"{}\"", _0)]
+    AssignToConstant(String),
+    #[fail(display = "Cannot redefine the builtin function \"{}\"", _0)]
+    DefineBuiltin(String),
     #[fail(display = "Cannot divide by zero")]
     DivideByZero,
+    #[fail(display = "The parameter \"{}\" is listed more than once", _0)]
+    DuplicateParameter(String),
+    #[fail(display = "Expected a variable assignment after \"global\"")]
+    ExpectedAssignment,
+    #[fail(display = "Expected a ':' with what to do otherwise")]
+    ExpectedElse,
     #[fail(display = "Expected EOF, found {}", _0)]
     ExpectedEOF(Token),
     #[fail(display = "Incorrect amount of arguments (Expected {}, got {})", _0, _1)]
     IncorrectArguments(usize, usize),
+    #[fail(display = "The result of {} is infinite here", _0)]
+    Infinite(&'static str),
+    #[fail(display = "Function parameters must be plain names")]
+    InvalidParameter,
     #[fail(display = "Invalid syntax")]
     InvalidSyntax,
     #[fail(display = "You may only do this on positive numbers")]
@@ -22,12 +41,22 @@ pub enum CalcError {
     NotAPrimitive(&'static str),
     #[fail(display = "You may only do this on whole numbers")]
     NotAWhole,
+    #[fail(display = "Input is outside the domain of {}", _0)]
+    OutOfDomain(&'static str),
+    #[fail(display = "The result doesn't fit in {}", _0)]
+    Overflow(Width),
     #[fail(display = "Parse error: {}", _0)]
     ParseError(#[cause] ParseError),
     #[fail(display = "A function definition cannot have multiple arguments")]
     SeparatorInDef,
+    #[fail(display = "{}", _1)]
+    Spanned(Span, Box<CalcError>),
     #[fail(display = "Too many levels deep. This could be an issue with endless recursion.")]
     TooDeep,
+    #[fail(display = "The result of {} would have more than {} digits", _0, _1)]
+    TooManyDigits(&'static str, u64),
+    #[fail(display = "Calculating {} would take more than {} steps", _0, _1)]
+    TooManySteps(&'static str, u64),
     #[fail(display = "Unclosed parentheses")]
     UnclosedParen,
     #[fail(display = "Unknown function \"{}\"\nHint: Cannot assume multiplication of variables because of ambiguity", _0)]
@@ -35,6 +64,32 @@ pub enum CalcError {
     #[fail(display = "Unknown variable \"{}\"", _0)]
     UnknownVariable(String)
 }
+impl CalcError {
+    /// Attaches the part of the input this error is about,
+    /// unless the error already knows a more specific location
+    pub fn at(self, span: Span) -> CalcError {
+        match self {
+            CalcError::Spanned(..) |
+            CalcError::ParseError(_) => self,
+            _ => CalcError::Spanned(span, Box::new(self))
+        }
+    }
+    /// Returns the part of the input this error is about, if known
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            CalcError::ParseError(ref err) => Some(err.span()),
+            CalcError::Spanned(span, _) => Some(span),
+            _ => None
+        }
+    }
+    /// Strips away any location, returning the error itself
+    pub fn unspanned(self) -> CalcError {
+        match self {
+            CalcError::Spanned(_, err) => *err,
+            _ => self
+        }
+    }
+}
 
 macro_rules! to_primitive {
     ($expr:expr, $type:ident, $primitive:expr) => {
@@ -45,415 +100,1009 @@ macro_rules! to_primitive {
     }
 }
 
+/// How much work a single calculation may do before it's refused
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The most digits a result may have
+    pub max_digits: u64,
+    /// The most digits a result that has to be approximated, like from exp or gamma, may have.
+    /// Approximating takes a lot more work per digit than calculating exactly does.
+    pub max_approx_digits: u64,
+    /// The most steps a loop, like the multiplications in a factorial, may take
+    pub max_steps: u64
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_digits: 500_000,
+            max_approx_digits: 20_000,
+            max_steps: 1_000_000
+        }
+    }
+}
+
+/// A user-defined function
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Function {
+    /// The names of the parameters, or `None` if the body uses `$1`, `$2`, ... instead
+    pub params: Option<Vec<String>>,
+    pub body: Expr
+}
+
+/// What running a statement did
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// An expression was calculated
+    Value(Number),
+    /// A variable was set, to the number it holds now
+    Assigned(String, Number),
+    /// A function was defined
+    Defined(String)
+}
+
 /// A Context for `calculate` to pass around to all its sub-functions
-pub struct Context<'a, I: Iterator<Item = Token>> {
+pub struct Context<'a> {
     level: u8,
+    /// How many functions the current statement called so far
+    steps: u64,
 
-    /// The tokens gotten by the parser
-    pub tokens: Peekable<I>,
-    /// A reference to a map of variables
-    pub variables: &'a mut HashMap<String, BigDecimal>,
+    /// The numeric backend to calculate with
+    pub mode: Mode,
+    /// How precise results that can't be exact should be
+    pub precision: Precision,
+    /// The fixed integer size operators wrap around to, if any
+    pub width: Option<Width>,
+    /// How big results may get
+    pub limits: Limits,
+    /// The variables local to the function call being calculated, if any
+    pub scope: Option<HashMap<String, Number>>,
+    /// A reference to a map of global variables
+    pub variables: &'a mut HashMap<String, Number>,
     /// A reference to a map of functions
-    pub functions: &'a mut HashMap<String, Vec<Token>>
+    pub functions: &'a mut HashMap<String, Function>
 }
-impl<'a, I: Iterator<Item = Token>> Context<'a, I> {
+impl<'a> Context<'a> {
     pub fn new(
-        tokens: Peekable<I>,
-        variables: &'a mut HashMap<String, BigDecimal>,
-        functions: &'a mut HashMap<String, Vec<Token>>
+        variables: &'a mut HashMap<String, Number>,
+        functions: &'a mut HashMap<String, Function>
     ) -> Self {
 
         Context {
             level: 0,
-            tokens: tokens,
+            steps: 0,
+            mode: Mode::default(),
+            precision: Precision::default(),
+            width: None,
+            limits: Limits::default(),
+            scope: None,
             variables: variables,
             functions: functions
         }
     }
-}
-
-/// Calculates the result in a recursive descent fashion
-pub fn calculate<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    if context.level == std::u8::MAX {
-        return Err(CalcError::TooDeep);
-    }
-
-    let expr1 = calc_level2(context)?;
-
-    if let Some(&Token::Xor) = context.tokens.peek() {
-        context.tokens.next();
-        let expr2 = calculate(context)?;
-
-        use num::ToPrimitive;
-        let primitive1 = to_primitive!(expr1, to_i64, "i64");
-        let primitive2 = to_primitive!(expr2, to_i64, "i64");
-
-        return Ok(BigDecimal::from(primitive1 ^ primitive2));
+    /// Looks up a variable in the current function call first, and then globally
+    pub fn get_variable(&self, name: &str) -> Option<&Number> {
+        self.scope.as_ref()
+            .and_then(|scope| scope.get(name))
+            .or_else(|| self.variables.get(name))
     }
-
-    match context.tokens.peek() {
-        Some(&Token::ParenClose) |
-        Some(&Token::Separator)
-        if context.level != 0 => Ok(expr1),
-
-        Some(_) => Err(CalcError::ExpectedEOF(context.tokens.next().unwrap())),
-        None => Ok(expr1)
+    /// Sets a variable, local to the current function call if there is one
+    pub fn set_variable(&mut self, name: String, val: Number) {
+        match self.scope {
+            Some(ref mut scope) => scope.insert(name, val),
+            None => self.variables.insert(name, val)
+        };
     }
 }
-fn calc_level2<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let expr1 = calc_level3(context)?;
-
-    if let Some(&Token::Or) = context.tokens.peek() {
-        context.tokens.next();
-        let expr2 = calc_level2(context)?;
 
-        use num::ToPrimitive;
-        let primitive1 = to_primitive!(expr1, to_i64, "i64");
-        let primitive2 = to_primitive!(expr2, to_i64, "i64");
-
-        return Ok(BigDecimal::from(primitive1 | primitive2));
+/// Runs a statement, telling assignments and definitions apart from expressions.
+/// Only the last of several statements separated by semicolons decides the outcome.
+pub fn run(context: &mut Context, expr: &Expr) -> Result<Outcome, CalcError> {
+    // Each statement may call functions up to max_steps times
+    context.steps = 0;
+    match expr.kind {
+        ExprKind::Sequence(ref exprs) => {
+            let (last, exprs) = exprs.split_last().unwrap();
+            for expr in exprs {
+                run(context, expr)?;
+            }
+            run(context, last)
+        },
+        ExprKind::Assign(ref name, _) |
+        ExprKind::AssignGlobal(ref name, _) => {
+            calculate(context, expr)?;
+            let value = context.get_variable(name).cloned().unwrap();
+            Ok(Outcome::Assigned(name.clone(), value))
+        },
+        ExprKind::Define(ref name, _, _) => {
+            calculate(context, expr)?;
+            Ok(Outcome::Defined(name.clone()))
+        },
+        _ => calculate(context, expr).map(Outcome::Value)
     }
-
-    Ok(expr1)
 }
-fn calc_level3<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let expr1 = calc_level4(context)?;
-
-    if let Some(&Token::And) = context.tokens.peek() {
-        context.tokens.next();
-        let expr2 = calc_level3(context)?;
-
-        use num::ToPrimitive;
-        let primitive1 = to_primitive!(expr1, to_i64, "i64");
-        let primitive2 = to_primitive!(expr2, to_i64, "i64");
-
-        return Ok(BigDecimal::from(primitive1 & primitive2));
-    }
-
-    Ok(expr1)
+/// Evaluates the expression tree built by `ast::parse`
+pub fn calculate(context: &mut Context, expr: &Expr) -> Result<Number, CalcError> {
+    calc_kind(context, &expr.kind).map_err(|err| err.at(expr.span))
 }
-fn calc_level4<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let mut expr1 = calc_level5(context)?;
-
-    loop {
-        use num::bigint::ToBigInt;
-        if let Some(&Token::BitshiftLeft) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level5(context)?;
-
-            use num::ToPrimitive;
-            let primitive2 = to_primitive!(expr2, to_usize, "usize");
-
-            require_whole(&expr1)?;
-            expr1 = BigDecimal::new(expr1.to_bigint().unwrap() << primitive2, 0);
-        } else if let Some(&Token::BitshiftRight) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level5(context)?;
-
-            use num::ToPrimitive;
-            let primitive2 = to_primitive!(expr2, to_usize, "usize");
-
-            require_whole(&expr1)?;
-            expr1 = BigDecimal::new(expr1.to_bigint().unwrap() >> primitive2, 0);
-        } else {
-            break;
+fn calc_kind(context: &mut Context, kind: &ExprKind) -> Result<Number, CalcError> {
+    match *kind {
+        ExprKind::Num(ref num) => {
+            let num = literal(context, num);
+            fit_width(context, num)
+        },
+        ExprKind::Var(ref name) => {
+            if let Some(constant) = math::constant(name, context.precision) {
+                return Ok(Number::Decimal(constant));
+            }
+            match context.get_variable(name) {
+                Some(val) => Ok(val.clone()),
+                None => Err(CalcError::UnknownVariable(name.clone()))
+            }
+        },
+        ExprKind::Unary(op, ref expr) => {
+            let expr = match (op, &expr.kind) {
+                // A negative literal like -128 fits in an i8 even though 128 on its own doesn't
+                (UnOp::Neg, ExprKind::Num(num)) => literal(context, num),
+                _ => calculate(context, expr)?
+            };
+            let result = calc_unary(context, op, expr)?;
+            fit_width(context, result)
+        },
+        ExprKind::Binary(ref expr1, op, ref expr2) => {
+            let left = calculate(context, expr1)?;
+            calc_operator(context, left, op, expr2)
+        },
+        ExprKind::Chain(ref first, ref rest) => {
+            let mut result = calculate(context, first)?;
+            for (i, &(op, ref right)) in rest.iter().enumerate() {
+                let value = calc_operator(context, result, op, right);
+                // Each step is about the chain up to its right side, and the whole chain
+                // gets its span from `calculate`
+                result = if i + 1 < rest.len() {
+                    value.map_err(|err| err.at(first.span.to(right.span)))?
+                } else {
+                    value?
+                };
+            }
+            Ok(result)
+        },
+        ExprKind::Conditional(ref cond, ref then, ref otherwise) => {
+            if calculate(context, cond)?.is_zero() {
+                calculate(context, otherwise)
+            } else {
+                calculate(context, then)
+            }
+        },
+        ExprKind::Call(ref name, ref args) => {
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                values.push(calculate(context, arg)?);
+            }
+            let result = calc_call(context, name, values)?;
+            fit_width(context, result)
+        },
+        ExprKind::Assign(ref name, ref expr) => {
+            if math::CONSTANTS.contains(&&**name) {
+                return Err(CalcError::AssignToConstant(name.clone()));
+            }
+            let val = calculate(context, expr)?;
+            context.set_variable(name.clone(), val);
+            Ok(Number::zero())
+        },
+        ExprKind::AssignGlobal(ref name, ref expr) => {
+            if math::CONSTANTS.contains(&&**name) {
+                return Err(CalcError::AssignToConstant(name.clone()));
+            }
+            let val = calculate(context, expr)?;
+            context.variables.insert(name.clone(), val);
+            Ok(Number::zero())
+        },
+        ExprKind::Sequence(ref exprs) => {
+            let mut result = Number::zero();
+            for expr in exprs {
+                result = calculate(context, expr)?;
+            }
+            Ok(result)
+        },
+        ExprKind::Define(ref name, ref params, ref body) => {
+            // Calls to these never reach a user-defined function
+            if BUILTINS.contains(&name.as_str()) {
+                return Err(CalcError::DefineBuiltin(name.clone()));
+            }
+            if let Some(ref params) = *params {
+                if let Some(param) = params.iter().find(|param| math::CONSTANTS.contains(&param.as_str())) {
+                    return Err(CalcError::AssignToConstant(param.clone()));
+                }
+            }
+            context.functions.insert(name.clone(), Function {
+                params: params.clone(),
+                body: (**body).clone()
+            });
+            Ok(Number::zero())
         }
     }
-
-    Ok(expr1)
 }
-fn calc_level5<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let mut expr1 = calc_level6(context)?;
-
-    loop {
-        if let Some(&Token::Add) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level6(context)?;
-
-            expr1 += expr2;
-        } else if let Some(&Token::Sub) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level6(context)?;
-
-            expr1 -= expr2;
-        } else {
-            break;
-        }
-    }
-
-    Ok(expr1)
+/// Converts a number written in the input to the representation used by the context
+fn literal(context: &Context, num: &BigDecimal) -> Number {
+    Number::Decimal(num.clone()).into_mode(context.mode, context.precision)
 }
-fn calc_level6<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let mut expr1 = calc_level7(context)?;
-
-    loop {
-        if let Some(&Token::Mul) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level7(context)?;
-
-            expr1 *= expr2;
-        } else if let Some(&Token::Div) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level7(context)?;
-
-            use num::Zero;
-            if expr2.is_zero() {
-                return Err(CalcError::DivideByZero);
-            }
-
-            expr1 = expr1 / expr2;
-        } else if let Some(&Token::Rem) = context.tokens.peek() {
-            context.tokens.next();
-            let expr2 = calc_level7(context)?;
-
-            use num::Zero;
-            if expr2.is_zero() {
-                return Err(CalcError::DivideByZero);
-            }
-
-            use num::bigint::ToBigInt;
-            expr1 = BigDecimal::new(expr1.to_bigint().unwrap() % expr2.to_bigint().unwrap(), 0);
-        } else {
-            break;
-        }
+/// Wraps a number or the result of an operator or function to the fixed integer size, if there is one
+fn fit_width(context: &Context, num: Number) -> Result<Number, CalcError> {
+    match context.width {
+        Some(width) => width.fit(&num, context.mode),
+        None => Ok(num)
     }
-
-    Ok(expr1)
 }
-fn calc_level7<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let expr1 = calc_level8(context)?;
-    if let Some(&Token::Pow) = context.tokens.peek() {
-        context.tokens.next();
-        let expr2 = calc_level7(context)?; // Right associative
-
-        return pow(expr1, expr2, None, 0);
+fn calc_unary(context: &mut Context, op: UnOp, expr: Number) -> Result<Number, CalcError> {
+    match op {
+        UnOp::DoubleFactorial => double_factorial(&expr, context.mode, context.limits),
+        UnOp::Factorial => factorial(&expr, context.mode, context.precision, context.limits),
+        UnOp::Neg => Ok(-expr),
+        UnOp::Not => {
+            require_whole(&expr)?;
+            // Two's complement, where !x is -x - 1
+            let int = expr.to_bigint().unwrap();
+            Ok(Number::from_bigint(-int - BigInt::one(), context.mode))
+        }
     }
-    Ok(expr1)
 }
-fn calc_level8<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    let expr = calc_level9(context)?;
-    if let Some(&Token::Factorial) = context.tokens.peek() {
-        context.tokens.next();
-
-        return factorial(expr, None, 0);
+/// Applies `op` to the already calculated left side and the expression on the right side
+fn calc_operator(context: &mut Context, left: Number, op: BinOp, right: &Expr) -> Result<Number, CalcError> {
+    match op {
+        BinOp::LogicalAnd | BinOp::LogicalOr => {
+            // Only calculate the right side if the left side doesn't decide the result
+            let left = !left.is_zero();
+            let result = if left == (op == BinOp::LogicalOr) {
+                left
+            } else {
+                !calculate(context, right)?.is_zero()
+            };
+            Ok(Number::from_bigint(BigInt::from(result as u8), context.mode))
+        },
+        BinOp::Pow if context.mode == Mode::Decimal && context.width.is_none() => {
+            // A power like 1/3 would be rounded as a decimal, and then (-8) ** (1/3) isn't an odd root anymore
+            let right = match literal_fraction(right) {
+                Some(power) => Number::Rational(power),
+                None => calculate(context, right)?
+            };
+            let result = calc_binary(context, left, op, right)?;
+            fit_width(context, result)
+        },
+        _ => {
+            let right = calculate(context, right)?;
+            let result = calc_binary(context, left, op, right)?;
+            fit_width(context, result)
+        }
     }
-    Ok(expr)
 }
-fn calc_level9<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    if let Some(&Token::Not) = context.tokens.peek() {
-        context.tokens.next();
-        use num::ToPrimitive;
-        let expr = calc_level9(context)?;
-        let primitive = to_primitive!(expr, to_i64, "i64");
-
-        return Ok(BigDecimal::from(!primitive));
+/// Reads a number literal or a fraction of them, like `2/3` or `-1/3`, without rounding it
+fn literal_fraction(expr: &Expr) -> Option<BigRational> {
+    match expr.kind {
+        ExprKind::Num(ref num) => Some(number::decimal_to_rational(num)),
+        ExprKind::Unary(UnOp::Neg, ref expr) => literal_fraction(expr).map(|num| -num),
+        ExprKind::Chain(ref first, ref rest) => match rest[..] {
+            [(BinOp::Div, ref denom)] => {
+                let numer = literal_fraction(first)?;
+                let denom = literal_fraction(denom)?;
+                if denom.is_zero() { None } else { Some(numer / denom) }
+            },
+            _ => None
+        },
+        _ => None
     }
-
-    Ok(calc_paren(context, None)?)
 }
-fn calc_paren<I: Iterator<Item = Token>>(context: &mut Context<I>, name: Option<String>) -> Result<BigDecimal, CalcError> {
-    if let Some(&Token::ParenOpen) = context.tokens.peek() {
-        context.tokens.next();
-
-        let mut args = Vec::new();
-
-        if let Some(&Token::ParenClose) = context.tokens.peek() {
-        } else {
-            context.level += 1;
-
-            args.push(calculate(context)?);
-
-            while let Some(&Token::Separator) = context.tokens.peek() {
-                context.tokens.next();
-                args.push(calculate(context)?);
-            }
+fn calc_binary(context: &mut Context, expr1: Number, op: BinOp, expr2: Number) -> Result<Number, CalcError> {
+    use num::ToPrimitive;
+    use num::bigint::ToBigInt;
+    match op {
+        BinOp::Xor | BinOp::Or | BinOp::And => {
+            require_whole(&expr1)?;
+            require_whole(&expr2)?;
+            let int1 = expr1.to_bigint().unwrap();
+            let int2 = expr2.to_bigint().unwrap();
 
-            context.level -= 1;
-        }
-        if Some(Token::ParenClose) != context.tokens.next() {
-            return Err(CalcError::UnclosedParen);
-        }
+            Ok(Number::from_bigint(match op {
+                BinOp::Xor => integer::bitwise(&int1, &int2, |a, b| a ^ b),
+                BinOp::Or => integer::bitwise(&int1, &int2, |a, b| a | b),
+                _ => integer::bitwise(&int1, &int2, |a, b| a & b)
+            }, context.mode))
+        },
+        BinOp::Equal | BinOp::NotEqual |
+        BinOp::Less | BinOp::LessEqual |
+        BinOp::Greater | BinOp::GreaterEqual => {
+            let ordering = expr1.compare(&expr2);
+            let result = match op {
+                BinOp::Equal => ordering == Ordering::Equal,
+                BinOp::NotEqual => ordering != Ordering::Equal,
+                BinOp::Less => ordering == Ordering::Less,
+                BinOp::LessEqual => ordering != Ordering::Greater,
+                BinOp::Greater => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less
+            };
+            Ok(Number::from_bigint(BigInt::from(result as u8), context.mode))
+        },
+        BinOp::BitshiftLeft | BinOp::BitshiftRight => {
+            let primitive2 = to_primitive!(expr2, to_usize, "usize");
 
-        macro_rules! usage {
-            ($expected:expr) => {
-                if args.len() != $expected {
-                    return Err(CalcError::IncorrectArguments($expected, args.len()));
+            require_whole(&expr1)?;
+            let int = expr1.to_bigint().unwrap();
+            Ok(Number::from_bigint(if op == BinOp::BitshiftLeft {
+                let bits = int.bits().saturating_add(primitive2);
+                if bits as f64 * std::f64::consts::LOG10_2 > context.limits.max_digits as f64 {
+                    return Err(CalcError::TooManyDigits("<<", context.limits.max_digits));
                 }
-            }
-        }
+                int << primitive2
+            } else if primitive2 >= int.bits() {
+                // Every bit is shifted out, leaving only the sign
+                if int.is_negative() { -BigInt::one() } else { BigInt::zero() }
+            } else {
+                // Round towards negative infinity, like an arithmetic shift
+                int.div_floor(&(BigInt::one() << primitive2))
+            }, context.mode))
+        },
+        BinOp::Pow => {
+            // The power keeps its mode, so an exact fraction stays exact
+            let expr1 = expr1.into_mode(context.mode, context.precision);
+            pow(expr1, expr2, context.precision, context.limits)
+        },
+        _ => {
+            let expr1 = expr1.into_mode(context.mode, context.precision);
+            let expr2 = expr2.into_mode(context.mode, context.precision);
+            match op {
+                BinOp::Add => Ok(expr1 + expr2),
+                BinOp::Sub => Ok(expr1 - expr2),
+                BinOp::Mul => Ok(expr1 * expr2),
+                BinOp::Div => {
+                    if expr2.is_zero() {
+                        return Err(CalcError::DivideByZero);
+                    }
 
-        if let Some(name) = name {
-            match &*name {
-                "abs" => {
-                    usage!(1);
-                    use num::Signed;
-                    args[0] = args[0].abs();
-                },
-                "pow" => {
-                    usage!(2);
-                    use num::Zero;
-                    args[0] = pow(mem::replace(&mut args[0], BigDecimal::zero()), args.remove(1), None, 0)?;
+                    Ok(expr1.div(expr2, context.precision))
                 },
-                _ => {
-                    let tokens = match context.functions.get(&name) {
-                        Some(tokens) => tokens.clone(),
-                        None => return Err(CalcError::UnknownFunction(name))
-                    };
-                    let len = args.len();
-                    for (i, arg) in args.into_iter().enumerate() {
-                        let mut name = String::with_capacity(2);
-                        name.push('$');
-                        name.push_str(&(i + 1).to_string());
-                        context.variables.insert(name, arg);
+                BinOp::Rem => {
+                    if expr2.is_zero() {
+                        return Err(CalcError::DivideByZero);
                     }
-                    let val = calculate(&mut Context {
-                        tokens: tokens.into_iter().peekable(),
-                        level: context.level + 1,
-                        variables: &mut context.variables,
-                        functions: &mut context.functions
-                    });
-                    for i in 1..len+1 {
-                        let mut name = String::with_capacity(2);
-                        name.push('$');
-                        name.push_str(&i.to_string());
-                        context.variables.remove(&name);
-                    }
-                    return val;
-                }
+
+                    // The remainder of two decimals always fits in a decimal, so this is exact
+                    let rem = Number::Rational(expr1.to_rational() % expr2.to_rational());
+                    Ok(match context.mode {
+                        Mode::Decimal => Number::Decimal(rem.to_exact_decimal().unwrap()),
+                        Mode::Rational => rem
+                    })
+                },
+                _ => unreachable!()
             }
-        } else {
-            usage!(1);
         }
+    }
+}
+/// The names of the functions `calc_call` has built in
+pub const BUILTINS: [&str; 44] = [
+    "abs", "acos", "asin", "atan", "atan2", "beta", "binomial", "cbrt", "ceil", "clamp", "cos",
+    "cosh", "exp", "floor", "frac", "gamma", "gcd", "isprime", "isqrt", "lcm", "lgamma", "ln",
+    "log", "log10", "log2", "max", "min", "minfactor", "modinv", "modpow", "nextprime", "pow",
+    "precision", "root", "round", "scale", "sign", "sin", "sinh", "sqrt", "tan", "tanh", "totient",
+    "trunc"
+];
 
-        if args.is_empty() {
-            use num::Zero;
-            return Ok(BigDecimal::zero())
-        } else {
-            return Ok(args.remove(0));
-        }
-    } else if name.is_none() {
-        if let Some(&Token::BlockName(_)) = context.tokens.peek() {
-            // Really ugly code, but we need to know the type *before* we walk out on it
-            if let Some(Token::BlockName(name)) = context.tokens.next() {
-                return calc_paren(context, Some(name));
+fn calc_call(context: &mut Context, name: &str, mut args: Vec<Number>) -> Result<Number, CalcError> {
+    macro_rules! usage {
+        ($expected:expr) => {
+            if args.len() != $expected {
+                return Err(CalcError::IncorrectArguments($expected, args.len()));
+            }
+        };
+        ($min:expr, $max:expr) => {
+            if args.len() < $min {
+                return Err(CalcError::IncorrectArguments($min, args.len()));
+            }
+            if args.len() > $max {
+                return Err(CalcError::IncorrectArguments($max, args.len()));
             }
         }
     }
 
-    Ok(get_number(context)?)
-}
-fn get_number<I: Iterator<Item = Token>>(context: &mut Context<I>) -> Result<BigDecimal, CalcError> {
-    match context.tokens.next() {
-        Some(Token::Num(num)) => Ok(num),
-        Some(Token::Sub) => {
-            Ok(-calc_paren(context, None)?)
-        },
-        Some(Token::VarAssign(name)) => {
-            if let Some(&Token::ParenOpen) = context.tokens.peek() {
-                context.tokens.next();
-                let mut fn_tokens = Vec::new();
-
-                let mut depth = 1;
-                loop {
-                    let token = match context.tokens.next() {
-                        Some(Token::Separator) if depth == 1 => return Err(CalcError::SeparatorInDef),
-                        Some(token) => token,
-                        None => return Err(CalcError::UnclosedParen)
-                    };
-                    if token == Token::ParenOpen {
-                        depth += 1;
-                    } else if token == Token::ParenClose {
-                        depth -= 1;
-                    }
-                    fn_tokens.push(token);
+    let precision = context.precision;
+    macro_rules! decimal {
+        ($index:expr) => {
+            args[$index].to_decimal(precision)
+        }
+    }
+    macro_rules! math {
+        ($function:path) => {{
+            usage!(1);
+            Ok(Number::Decimal($function(&decimal!(0), precision)?))
+        }};
+        ($function:path, 2) => {{
+            usage!(2);
+            Ok(Number::Decimal($function(&decimal!(0), &decimal!(1), precision)?))
+        }}
+    }
 
-                    if depth == 0 {
-                        break;
-                    } else if depth == std::u8::MAX {
-                        return Err(CalcError::TooDeep);
-                    }
-                }
+    macro_rules! whole {
+        ($index:expr) => {{
+            require_whole(&args[$index])?;
+            args[$index].to_bigint().unwrap()
+        }}
+    }
+    macro_rules! whole_positive {
+        ($index:expr) => {{
+            require_positive(&args[$index])?;
+            whole!($index)
+        }}
+    }
+    let mode = context.mode;
+    let int = |int: BigInt| Ok(Number::from_bigint(int, mode));
+    let boolean = |boolean: bool| int(BigInt::from(boolean as u8));
 
-                context.functions.insert(name, fn_tokens);
+    match name {
+        "abs" => {
+            usage!(1);
+            Ok(args[0].abs())
+        },
+        "floor" => {
+            usage!(1);
+            Ok(args[0].round(0, Rounding::Floor))
+        },
+        "ceil" => {
+            usage!(1);
+            Ok(args[0].round(0, Rounding::Ceiling))
+        },
+        "round" => {
+            usage!(1, 2);
+            let places = if args.len() == 2 {
+                require_whole(&args[1])?;
+                to_primitive!(args[1], to_i64, "i64")
             } else {
-                let val = calculate(context)?;
-                context.variables.insert(name, val);
+                0
+            };
+            // Rounding to more places than the number has changes nothing, however many there are
+            let exact = places >= 0 && args[0].scale().is_some_and(|scale| scale <= places as u64);
+            if !exact && places.unsigned_abs() > context.limits.max_digits {
+                return Err(CalcError::TooManyDigits("round", context.limits.max_digits));
             }
-            use num::Zero;
-            Ok(BigDecimal::zero())
-        },
-        Some(Token::VarGet(name)) => {
-            Ok(
-                match context.variables.get(&name) {
-                    Some(val) => val.clone(),
-                    None => return Err(CalcError::UnknownVariable(name))
+            Ok(args[0].round(places, Rounding::HalfUp))
+        },
+        "trunc" => {
+            usage!(1);
+            Ok(args[0].round(0, Rounding::Truncate))
+        },
+        "frac" => {
+            usage!(1);
+            let whole = args[0].round(0, Rounding::Truncate);
+            Ok(args.remove(0) - whole)
+        },
+        "sign" => {
+            usage!(1);
+            int(match args[0].sign() {
+                Sign::Minus => -BigInt::one(),
+                Sign::NoSign => BigInt::zero(),
+                Sign::Plus => BigInt::one()
+            })
+        },
+        "min" | "max" => {
+            usage!(1, usize::MAX);
+            let wanted = if name == "min" { Ordering::Less } else { Ordering::Greater };
+            let mut args = args.into_iter();
+            let mut result = args.next().unwrap();
+            for arg in args {
+                if arg.compare(&result) == wanted {
+                    result = arg;
                 }
-            )
+            }
+            Ok(result)
+        },
+        "clamp" => {
+            usage!(3);
+            let max = args.remove(2);
+            let min = args.remove(1);
+            let num = args.remove(0);
+            if min.compare(&max) == Ordering::Greater {
+                return Err(CalcError::OutOfDomain("clamp"));
+            }
+            Ok(if num.compare(&min) == Ordering::Less {
+                min
+            } else if num.compare(&max) == Ordering::Greater {
+                max
+            } else {
+                num
+            })
+        },
+        "scale" => {
+            usage!(1);
+            match args[0].scale() {
+                Some(scale) => int(BigInt::from(scale)),
+                None => Err(CalcError::Infinite("scale"))
+            }
+        },
+        "precision" => {
+            usage!(1);
+            // How many significant digits there are
+            let num = match args[0].to_exact_decimal() {
+                Some(num) => number::trim_zeros(num),
+                None => return Err(CalcError::Infinite("precision"))
+            };
+            let (int_val, _) = num.as_bigint_and_exponent();
+            int(BigInt::from(int_val.abs().to_str_radix(10).len()))
+        },
+        "gamma" => {
+            usage!(1);
+            gamma(&args[0], context.mode, precision, context.limits)
+        },
+        "beta" | "atan2" => {
+            usage!(2);
+            let name = if name == "beta" { "beta" } else { "atan2" };
+            check_approx(name, args[0].magnitude().max(args[1].magnitude()), context.limits)?;
+            let function = if name == "beta" { math::beta } else { math::atan2 };
+            Ok(Number::Decimal(function(&decimal!(0), &decimal!(1), precision)?))
+        },
+        "sqrt" => math!(math::sqrt),
+        "cbrt" => {
+            usage!(1);
+            root(&args[0], &Number::from_bigint(BigInt::from(3), Mode::Decimal), precision, context.limits)
+        },
+        "root" => {
+            usage!(2);
+            root(&args[0], &args[1], precision, context.limits)
+        },
+        "exp" => {
+            usage!(1);
+            // e^x has about x * log10(e) digits, and e^-x is tiny no matter how big x is
+            if args[0].sign() == Sign::Plus {
+                check_approx("exp", exp_digits(&args[0]), context.limits)?;
+            }
+            Ok(Number::Decimal(math::exp(&decimal!(0), precision)?))
+        },
+        "ln" => math!(math::ln),
+        "log" => math!(math::log, 2),
+        "log2" => {
+            usage!(1);
+            Ok(Number::Decimal(math::log(&decimal!(0), &BigDecimal::from(2), precision)?))
+        },
+        "log10" => {
+            usage!(1);
+            Ok(Number::Decimal(math::log(&decimal!(0), &BigDecimal::from(10), precision)?))
+        },
+        "sin" | "cos" | "tan" | "lgamma" => {
+            usage!(1);
+            // These take as many more digits as x has before the point
+            let name = match name { "sin" => "sin", "cos" => "cos", "tan" => "tan", _ => "lgamma" };
+            check_approx(name, args[0].magnitude(), context.limits)?;
+            let function = match name { "sin" => math::sin, "cos" => math::cos, "tan" => math::tan, _ => math::lgamma };
+            Ok(Number::Decimal(function(&decimal!(0), precision)?))
+        },
+        "asin" => math!(math::asin),
+        "acos" => math!(math::acos),
+        "atan" => math!(math::atan),
+        "sinh" | "cosh" => {
+            usage!(1);
+            // Both are about e^|x| / 2
+            let name = if name == "sinh" { "sinh" } else { "cosh" };
+            check_approx(name, exp_digits(&args[0].abs()), context.limits)?;
+            let function = if name == "sinh" { math::sinh } else { math::cosh };
+            Ok(Number::Decimal(function(&decimal!(0), precision)?))
+        },
+        "tanh" => math!(math::tanh),
+        "gcd" => {
+            usage!(2);
+            int(whole!(0).gcd(&whole!(1)))
+        },
+        "lcm" => {
+            usage!(2);
+            let (num1, num2) = (whole!(0), whole!(1));
+            if num1.is_zero() || num2.is_zero() {
+                return int(BigInt::zero());
+            }
+            int(num1.lcm(&num2))
+        },
+        "modpow" => {
+            usage!(3);
+            int(integer::modpow(&whole!(0), &whole!(1), &whole!(2))?)
+        },
+        "modinv" => {
+            usage!(2);
+            int(integer::modinv(&whole!(0), &whole!(1))?)
+        },
+        "isprime" => {
+            usage!(1);
+            boolean(integer::is_prime(&whole!(0)))
+        },
+        "nextprime" => {
+            usage!(1);
+            int(integer::next_prime(&whole!(0), context.limits.max_steps)?)
         },
-        _ => Err(CalcError::InvalidSyntax)
+        "minfactor" => {
+            usage!(1);
+            // The smallest prime factor, so dividing by it repeatedly walks the factorization.
+            // The whole factorization is written by the REPL's :factor.
+            let num = whole!(0);
+            if num.is_zero() {
+                return Err(CalcError::OutOfDomain("minfactor"));
+            }
+            match integer::factorize(&num, context.limits.max_steps)?.into_iter().next() {
+                Some((prime, _)) => int(prime),
+                None => int(BigInt::one())
+            }
+        },
+        "binomial" => {
+            usage!(2);
+            let (n, k) = (whole_positive!(0), whole!(1));
+            // n^k / k! is a bit more than the result
+            let k_approx = args[1].approximate().min(args[0].approximate() - args[1].approximate());
+            if k_approx > 0.0 && k_approx * args[0].magnitude() - factorial_digits(k_approx) > context.limits.max_digits as f64 {
+                return Err(CalcError::TooManyDigits("binomial", context.limits.max_digits));
+            }
+            int(integer::binomial(&n, &k, context.limits.max_steps)?)
+        },
+        "totient" => {
+            usage!(1);
+            let num = whole_positive!(0);
+            if num.is_zero() {
+                return Err(CalcError::OutOfDomain("totient"));
+            }
+            int(integer::totient(&num, context.limits.max_steps)?)
+        },
+        "isqrt" => {
+            usage!(1);
+            int(math::isqrt(&whole_positive!(0)))
+        },
+        "pow" => {
+            usage!(2);
+            let power = args.remove(1).into_mode(context.mode, context.precision);
+            pow(args.remove(0).into_mode(context.mode, context.precision), power, context.precision, context.limits)
+        },
+        _ => {
+            if context.level == u8::MAX {
+                return Err(CalcError::TooDeep);
+            }
+            // Recursion that doesn't go deep can still call functions endlessly often
+            context.steps += 1;
+            if context.steps > context.limits.max_steps {
+                return Err(CalcError::TooManySteps("these function calls", context.limits.max_steps));
+            }
+            let function = match context.functions.get(name) {
+                Some(function) => function.clone(),
+                None => return Err(CalcError::UnknownFunction(name.to_string()))
+            };
+            let names = match function.params {
+                Some(params) => {
+                    if params.len() != args.len() {
+                        return Err(CalcError::IncorrectArguments(params.len(), args.len()));
+                    }
+                    params
+                },
+                None => (1..args.len() + 1).map(|i| format!("${}", i)).collect()
+            };
+            // Every call gets its own scope, which only sees the arguments and global variables
+            let mut call = Context {
+                level: context.level + 1,
+                steps: context.steps,
+                mode: context.mode,
+                precision: context.precision,
+                width: context.width,
+                limits: context.limits,
+                scope: Some(names.into_iter().zip(args).collect()),
+                variables: context.variables,
+                functions: context.functions
+            };
+            let val = calculate(&mut call, &function.body);
+            context.steps = call.steps;
+            // The body's spans point into the input it was defined in
+            val.map_err(CalcError::unspanned)
+        }
     }
 }
-fn require_whole(num: &BigDecimal) -> Result<(), CalcError> {
-    if num.with_scale(0) == *num {
+fn require_whole(num: &Number) -> Result<(), CalcError> {
+    if num.is_whole() {
         Ok(())
     } else {
         Err(CalcError::NotAWhole)
     }
 }
-fn require_positive(num: &BigDecimal) -> Result<(), CalcError> {
+fn require_positive(num: &Number) -> Result<(), CalcError> {
     match num.sign() {
         Sign::NoSign |
         Sign::Plus => Ok(()),
         Sign::Minus => Err(CalcError::NotAPositive)
     }
 }
-/// Calculates the factorial of `num`
-pub fn factorial(num: BigDecimal, acc: Option<BigDecimal>, times: u8) -> Result<BigDecimal, CalcError> {
-    if times == std::u8::MAX {
-        return Err(CalcError::TooDeep);
+/// Roughly calculates log10(n!), using Stirling's approximation
+fn factorial_digits(n: f64) -> f64 {
+    if n < 2.0 {
+        return 0.0;
+    }
+    (n * n.ln() - n + (2.0 * std::f64::consts::PI * n).ln() / 2.0) / std::f64::consts::LN_10
+}
+/// Refuses to approximate a result with about `digits` digits, if that's more than the limits allow
+fn check_approx(name: &'static str, digits: f64, limits: Limits) -> Result<(), CalcError> {
+    let max = limits.max_digits.min(limits.max_approx_digits);
+    // Estimates of results too big for an f64 can come out as NaN
+    if digits.is_nan() || digits > max as f64 {
+        return Err(CalcError::TooManyDigits(name, max));
+    }
+    Ok(())
+}
+/// Roughly how many digits e^`num` has
+fn exp_digits(num: &Number) -> f64 {
+    num.approximate() * std::f64::consts::LOG10_E
+}
+/// Calculates the factorial of `num`. Anything but whole numbers goes through the gamma function.
+pub fn factorial(num: &Number, mode: Mode, precision: Precision, limits: Limits) -> Result<Number, CalcError> {
+    if !num.is_whole() {
+        return gamma(&(num.clone() + Number::one()), mode, precision, limits);
+    }
+    if num.sign() == Sign::Minus {
+        return Err(CalcError::Infinite("factorial"));
+    }
+
+    let n = to_primitive!(num, to_u64, "u64");
+    if n > limits.max_steps {
+        return Err(CalcError::TooManySteps("factorial", limits.max_steps));
+    }
+    if factorial_digits(n as f64) > limits.max_digits as f64 {
+        return Err(CalcError::TooManyDigits("factorial", limits.max_digits));
+    }
+    Ok(Number::from_bigint(integer::product(1, n), mode))
+}
+/// Calculates the double factorial of `num`, which only multiplies every other number
+pub fn double_factorial(num: &Number, mode: Mode, limits: Limits) -> Result<Number, CalcError> {
+    require_whole(num)?;
+    // (-1)!! is 1, like 0!! is
+    if num.compare(&-Number::one()) == Ordering::Less {
+        return Err(CalcError::OutOfDomain("double factorial"));
     }
-    require_whole(&num)?;
-    require_positive(&num)?;
 
-    use num::{Zero, One};
-    if num.is_zero() {
-        Ok(acc.unwrap_or_else(BigDecimal::one))
+    let n = to_primitive!(num.abs(), to_u64, "u64");
+    if n / 2 > limits.max_steps {
+        return Err(CalcError::TooManySteps("double factorial", limits.max_steps));
+    }
+    // n!! is about as big as sqrt(n!)
+    if factorial_digits(n as f64) / 2.0 > limits.max_digits as f64 {
+        return Err(CalcError::TooManyDigits("double factorial", limits.max_digits));
+    }
+    let half = n / 2;
+    Ok(Number::from_bigint(if n.is_even() {
+        // 2 * 4 * ... * n = 2^(n/2) * (n/2)!
+        integer::product(1, half) << half as usize
     } else {
-        let acc = acc.unwrap_or_else(BigDecimal::one);
-        let acc = Some(acc * &num);
-        // Y THIS NO TAILCALL OPTIMIZE
-        factorial(num - BigDecimal::one(), acc, times + 1)
+        // 1 * 3 * ... * n = n! / (2 * 4 * ... * (n - 1))
+        integer::product(half + 1, n) / (BigInt::one() << half as usize)
+    }, mode))
+}
+/// Calculates the gamma function of `num`
+pub fn gamma(num: &Number, mode: Mode, precision: Precision, limits: Limits) -> Result<Number, CalcError> {
+    // Whole numbers can be exact
+    if num.is_whole() && num.sign() == Sign::Plus {
+        return factorial(&(num.clone() - Number::one()), mode, precision, limits);
     }
+    check_approx("gamma", factorial_digits(num.approximate() - 1.0), limits)?;
+    Ok(Number::Decimal(math::gamma(&num.to_decimal(precision), precision)?))
 }
-/// Calculates `num` to the power of `power`
-pub fn pow(num: BigDecimal, power: BigDecimal, acc: Option<BigDecimal>, times: u8) -> Result<BigDecimal, CalcError> {
-    if times == std::u8::MAX {
-        return Err(CalcError::TooDeep);
-    }
-    require_positive(&num)?;
-    require_whole(&power)?;
-
-    use num::{Zero, One};
-    match power.sign() {
-        Sign::NoSign => {
-            Ok(acc.unwrap_or_else(|| num.clone()))
-        },
-        Sign::Plus => {
-            let one = BigDecimal::one();
-            let two = BigDecimal::from(2);
-
-            if (&power % &two).is_zero() {
-                let acc = Some(acc.unwrap_or(one) * &num * &num);
-                pow(num, power / two, acc, times + 1)
-            } else {
-                let power = power - &one;
-                let acc = Some(acc.unwrap_or(one) * &num);
-                pow(num, power, acc, times + 1)
+/// Calculates the `n`th root of `num`
+pub fn root(num: &Number, n: &Number, precision: Precision, limits: Limits) -> Result<Number, CalcError> {
+    if n.is_zero() {
+        return Err(CalcError::OutOfDomain("root"));
+    }
+    // Roots that come out exact, like root(27, 3), shouldn't be approximated
+    if let Some(degree) = n.abs().to_usize() {
+        let exact = match num.sign() {
+            Sign::Minus if degree.is_odd() => num.abs().exact_root(degree).map(|root| -root),
+            Sign::Minus => None,
+            _ => num.exact_root(degree)
+        };
+        if let Some(root) = exact {
+            return Ok(if n.sign() == Sign::Minus { Number::one().div(root, precision) } else { root });
+        }
+    }
+    if !num.is_zero() {
+        check_approx("root", num.magnitude() / n.approximate(), limits)?;
+    }
+    Ok(Number::Decimal(math::root(&num.to_decimal(precision), &n.to_decimal(precision), precision)?))
+}
+/// Calculates `num` to the power of `power`.
+/// Negative and fractional powers are rounded according to `precision`.
+pub fn pow(num: Number, power: Number, precision: Precision, limits: Limits) -> Result<Number, CalcError> {
+    if num.sign() == Sign::Minus {
+        // Only whole powers and odd roots of negative numbers are real
+        let exp = power.to_rational();
+        if exp.denom().is_even() {
+            return Err(CalcError::OutOfDomain("pow"));
+        }
+        let result = pow(-num, power, precision, limits)?;
+        return Ok(if exp.numer().is_odd() { -result } else { result });
+    }
+    if !power.is_whole() {
+        if num.is_zero() {
+            return if power.sign() == Sign::Minus { Err(CalcError::DivideByZero) } else { Ok(num) };
+        }
+        // Roots that come out exact, like 8 ** (1/3), shouldn't be approximated
+        let exp = power.to_rational();
+        if let Some(root) = exp.denom().to_usize().and_then(|n| num.exact_root(n)) {
+            return pow(root, Number::from_bigint(exp.numer().clone(), Mode::Decimal), precision, limits);
+        }
+        check_approx("pow", num.magnitude() * power.approximate(), limits)?;
+        return Ok(Number::Decimal(math::pow(&num.to_decimal(precision), &power.to_decimal(precision), precision)?));
+    }
+
+    if power.sign() == Sign::Minus {
+        if num.is_zero() {
+            return Err(CalcError::DivideByZero);
+        }
+        let result = pow(num, -power, precision, limits)?;
+        return Ok(Number::one().div(result, precision));
+    }
+    // Powers of 0 and 1 never grow, no matter how big the power is
+    if !power.is_zero() && (num.is_zero() || num.compare(&Number::one()) == Ordering::Equal) {
+        return Ok(num);
+    }
+
+    let num = match num {
+        Number::Decimal(num) => Number::Decimal(number::trim_zeros(num)),
+        num => num
+    };
+    let digits = num.digits() * power.approximate();
+    if digits > limits.max_digits as f64 {
+        return Err(CalcError::TooManyDigits("pow", limits.max_digits));
+    }
+    Ok(num.pow(to_primitive!(power, to_usize, "usize")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_and_calc;
+
+    /// Calculates `input` in a new context, after `setup` changes it
+    fn calc_with<F: FnOnce(&mut Context)>(input: &str, setup: F) -> Result<String, CalcError> {
+        let mut variables = HashMap::new();
+        let mut functions = HashMap::new();
+        let mut context = Context::new(&mut variables, &mut functions);
+        setup(&mut context);
+        match parse_and_calc(input, &mut context)? {
+            Outcome::Value(result) => Ok(result.to_string()),
+            outcome => panic!("{} gave {:?}", input, outcome)
+        }
+    }
+    fn calc(input: &str) -> String {
+        calc_with(input, |_| ()).unwrap_or_else(|err| panic!("{} failed: {}", input, err))
+    }
+    fn error(input: &str) -> CalcError {
+        match calc_with(input, |_| ()) {
+            Ok(result) => panic!("{} gave {}", input, result),
+            Err(err) => err
+        }
+    }
+    fn width(bits: usize, signed: bool, strict: bool) -> impl FnOnce(&mut Context) {
+        move |context: &mut Context| context.width = Some(Width { bits, signed, strict })
+    }
+    /// Runs `test` with as much stack as the main thread usually has, which the depth limits count on
+    fn with_main_stack<F: FnOnce() + Send + 'static>(test: F) {
+        std::thread::Builder::new().stack_size(8 * 1024 * 1024).spawn(test).unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn operators() {
+        let cases = [
+            ("1 + 2 * 3", "7"),
+            ("(1 + 2) * 3", "9"),
+            ("10 - 4 - 3", "3"),
+            ("7 / 2", "3.5"),
+            ("2 ** 3 ** 2", "512"),
+            ("2 ** -2", "0.25"),
+            ("(-8) ** (1/3)", "-2"),
+            ("5.5 % 2", "1.5"),
+            ("-7 % 3", "-1"),
+            ("5!", "120"),
+            ("6!!", "48"),
+            ("~5", "-6"),
+            ("5 & 3", "1"),
+            ("5 | 3", "7"),
+            ("5 ^ 3", "6"),
+            ("1 << 4", "16"),
+            ("-17 >> 2", "-5"),
+            ("3 > 2", "1"),
+            ("2 >= 3", "0"),
+            ("1 == 1.0", "1"),
+            ("1 != 1", "0"),
+            ("0 && 1 / 0", "0"),
+            ("1 || 1 / 0", "1"),
+            ("0 ? 1 / 0 : 3", "3"),
+            ("2(1 + 1)", "4")
+        ];
+        for &(input, expected) in cases.iter() {
+            assert_eq!(calc(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn rational_remainder() {
+        let rational = |context: &mut Context| context.mode = Mode::Rational;
+        assert_eq!(calc_with("7/2 % 1", rational).unwrap(), "1/2");
+        assert_eq!(calc_with("-7/2 % 2", rational).unwrap(), "-3/2");
+        assert_eq!(calc_with("1/3 % 1/4", rational).unwrap(), "1/12");
+        assert!(calc_with("1/3 % 0", rational).is_err());
+    }
+
+    #[test]
+    fn rounding() {
+        let rounded = |input: &str, rounding: Rounding| {
+            calc_with(input, |context| context.precision = Precision { scale: 2, rounding }).unwrap()
+        };
+        assert_eq!(rounded("2 / 3", Rounding::Truncate), "0.66");
+        assert_eq!(rounded("2 / 3", Rounding::HalfUp), "0.67");
+        assert_eq!(rounded("-2 / 3", Rounding::Floor), "-0.67");
+        assert_eq!(rounded("-2 / 3", Rounding::Ceiling), "-0.66");
+        assert_eq!(rounded("1 / 8", Rounding::HalfEven), "0.12");
+        assert_eq!(rounded("1 / 8", Rounding::HalfUp), "0.13");
+    }
+
+    #[test]
+    fn widths() {
+        assert_eq!(calc_with("255 + 1", width(8, false, false)).unwrap(), "0");
+        assert_eq!(calc_with("0 - 1", width(8, false, false)).unwrap(), "255");
+        assert_eq!(calc_with("127 + 1", width(8, true, false)).unwrap(), "-128");
+        assert_eq!(calc_with("-128", width(8, true, false)).unwrap(), "-128");
+        assert_eq!(calc_with("7 / 2", width(8, true, false)).unwrap(), "3");
+        match calc_with("255 + 1", width(8, false, true)).map_err(CalcError::unspanned) {
+            Err(CalcError::Overflow(_)) => (),
+            result => panic!("255 + 1 in a strict u8 gave {:?}", result)
+        }
+    }
+
+    #[test]
+    fn scopes() {
+        assert_eq!(calc("x = 1; f(y) = x = y; f(5); x"), "1");
+        assert_eq!(calc("x = 1; f() = global x = 2; f(); x"), "2");
+        assert_eq!(calc("x = 3; f(x) = x * 2; f(4) + x"), "11");
+        assert_eq!(calc("f = ($1 + $2); f(1, 2)"), "3");
+        match error("f(a) = a; f(1); a").unspanned() {
+            CalcError::UnknownVariable(ref name) if name == "a" => (),
+            err => panic!("the parameter leaked out of the call: {}", err)
+        }
+    }
+
+    #[test]
+    fn limits() {
+        with_main_stack(|| {
+            let chain = format!("{}1", "1 + ".repeat(50_000));
+            assert_eq!(calc(&chain), "50001");
+
+            let nested = format!("{}1{}", "(".repeat(300), ")".repeat(300));
+            match error(&nested).unspanned() {
+                CalcError::TooDeep => (),
+                err => panic!("300 parentheses gave {}", err)
             }
-        },
-        Sign::Minus => {
-            use num::Signed;
-            pow(BigDecimal::one() / num, power.abs(), acc, times + 1)
+            match error("f(x) = f(x); f(1)").unspanned() {
+                CalcError::TooDeep => (),
+                err => panic!("endless recursion gave {}", err)
+            }
+
+            let few_steps = |context: &mut Context| context.limits.max_steps = 100;
+            let fib = "fib(n) = n < 2 ? n : fib(n - 1) + fib(n - 2)";
+            assert_eq!(calc_with(&format!("{}; fib(8)", fib), few_steps).unwrap(), "21");
+            match calc_with(&format!("{}; fib(20)", fib), few_steps).map_err(CalcError::unspanned) {
+                Err(CalcError::TooManySteps(..)) => (),
+                result => panic!("fib(20) in 100 steps gave {:?}", result)
+            }
+            match error("10 ** 10 ** 10").unspanned() {
+                CalcError::TooManyDigits(..) => (),
+                err => panic!("10 ** 10 ** 10 gave {}", err)
+            }
+        });
+    }
+
+    #[test]
+    fn spans() {
+        let cases = [
+            ("1 + 2 / 0 + 3", Span::new(4, 9)),
+            ("1 + nope", Span::new(4, 8)),
+            ("2 * nope(1)", Span::new(4, 11)),
+            ("sqrt(-1) + 1", Span::new(0, 8)),
+            ("1 + (2", Span::new(4, 5)),
+            ("f(x, x) = x", Span::new(5, 6))
+        ];
+        for &(input, span) in cases.iter() {
+            assert_eq!(error(input).span(), Some(span), "{}", input);
         }
     }
 }
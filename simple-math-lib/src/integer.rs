@@ -0,0 +1,393 @@
+use calculator::CalcError;
+use num::bigint::{BigUint, ToBigInt};
+use num::{self, BigInt, Integer, One, Signed, ToPrimitive, Zero};
+use std::fmt::Write;
+
+/// The primes used for trial division and as Miller-Rabin witnesses
+const SMALL_PRIMES: [u32; 25] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41,
+    43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97
+];
+
+/// Calculates `base` to the power of `exp`, modulo `modulus`
+pub fn modpow(base: &BigInt, exp: &BigInt, modulus: &BigInt) -> Result<BigInt, CalcError> {
+    if modulus.is_zero() {
+        return Err(CalcError::DivideByZero);
+    }
+    if exp.is_negative() {
+        let inverse = modinv(base, modulus)?;
+        return modpow(&inverse, &-exp, modulus);
+    }
+    let modulus = modulus.abs();
+
+    let mut result = BigInt::one().mod_floor(&modulus);
+    let mut base = base.mod_floor(&modulus);
+    let mut exp = exp.clone();
+    let two = BigInt::from(2);
+    while !exp.is_zero() {
+        if exp.is_odd() {
+            result = (result * &base).mod_floor(&modulus);
+        }
+        base = (&base * &base).mod_floor(&modulus);
+        exp = exp / &two;
+    }
+    Ok(result)
+}
+/// Finds the number that multiplied with `num` is 1, modulo `modulus`
+pub fn modinv(num: &BigInt, modulus: &BigInt) -> Result<BigInt, CalcError> {
+    if modulus.is_zero() {
+        return Err(CalcError::DivideByZero);
+    }
+    let modulus = modulus.abs();
+
+    // Extended euclidean algorithm
+    let (mut old_r, mut r) = (num.mod_floor(&modulus), modulus.clone());
+    let (mut old_s, mut s) = (BigInt::one(), BigInt::zero());
+    while !r.is_zero() {
+        let quotient = old_r.div_floor(&r);
+        let new_r = &old_r - &quotient * &r;
+        old_r = r;
+        r = new_r;
+        let new_s = &old_s - &quotient * &s;
+        old_s = s;
+        s = new_s;
+    }
+
+    if !old_r.is_one() {
+        return Err(CalcError::OutOfDomain("modinv"));
+    }
+    Ok(old_s.mod_floor(&modulus))
+}
+
+/// Checks if `num` is a prime, using Miller-Rabin.
+/// This is exact for anything below 3 * 10^24, and practically certain above that.
+pub fn is_prime(num: &BigInt) -> bool {
+    if *num < BigInt::from(2) {
+        return false;
+    }
+    for &prime in SMALL_PRIMES.iter() {
+        let prime = BigInt::from(prime);
+        if *num == prime {
+            return true;
+        }
+        if num.is_multiple_of(&prime) {
+            return false;
+        }
+    }
+
+    // Write num - 1 as d * 2^s
+    let one = BigInt::one();
+    let minus_one = num - &one;
+    let mut d = minus_one.clone();
+    let mut s = 0;
+    while d.is_even() {
+        d = d >> 1;
+        s += 1;
+    }
+
+    'witness: for &witness in SMALL_PRIMES.iter() {
+        let mut x = modpow(&BigInt::from(witness), &d, num).unwrap();
+        if x == one || x == minus_one {
+            continue;
+        }
+        for _ in 1..s {
+            x = (&x * &x).mod_floor(num);
+            if x == minus_one {
+                continue 'witness;
+            }
+        }
+        return false;
+    }
+    true
+}
+/// Finds the smallest prime bigger than `num`.
+/// Testing a candidate counts as one step for each of its bits, and the search gives up after `max_steps`.
+pub fn next_prime(num: &BigInt, max_steps: u64) -> Result<BigInt, CalcError> {
+    let two = BigInt::from(2);
+    if *num < two {
+        return Ok(two);
+    }
+    let mut candidate = num + BigInt::one();
+    if candidate.is_even() && candidate != two {
+        candidate = candidate + BigInt::one();
+    }
+    let mut steps = 0;
+    while !is_prime(&candidate) {
+        steps += candidate.bits() as u64;
+        if steps > max_steps {
+            return Err(CalcError::TooManySteps("nextprime", max_steps));
+        }
+        candidate = candidate + &two;
+    }
+    Ok(candidate)
+}
+
+/// Finds a non-trivial factor of the odd composite `num`, using Pollard's rho.
+/// Each round counts as a step, and it gives up once `steps` is more than `max_steps`.
+fn pollard_rho(num: &BigInt, steps: &mut u64, max_steps: u64) -> Result<BigInt, CalcError> {
+    let mut c = BigInt::one();
+    loop {
+        let step = |x: &BigInt| (x * x + &c).mod_floor(num);
+        let mut x = BigInt::from(2);
+        let mut y = x.clone();
+        let mut divisor = BigInt::one();
+        while divisor.is_one() {
+            *steps += 1;
+            if *steps > max_steps {
+                return Err(CalcError::TooManySteps("the prime factors", max_steps));
+            }
+            x = step(&x);
+            y = step(&step(&y));
+            divisor = (&x - &y).abs().gcd(num);
+        }
+        if divisor != *num {
+            return Ok(divisor);
+        }
+        c = c + BigInt::one();
+    }
+}
+/// Splits the positive number `num` into its prime factors, each with how many times it occurs.
+/// The factors are sorted from smallest to biggest. Big factors take steps to find, up to `max_steps`.
+pub fn factorize(num: &BigInt, max_steps: u64) -> Result<Vec<(BigInt, usize)>, CalcError> {
+    let mut factors = Vec::new();
+    let mut num = num.abs();
+
+    for &prime in SMALL_PRIMES.iter() {
+        let prime = BigInt::from(prime);
+        let mut count = 0;
+        while !num.is_zero() && num.is_multiple_of(&prime) {
+            num = num / &prime;
+            count += 1;
+        }
+        if count > 0 {
+            factors.push((prime, count));
+        }
+    }
+
+    let mut steps = 0;
+    let mut remaining = Vec::new();
+    if num > BigInt::one() {
+        remaining.push(num);
+    }
+    while let Some(num) = remaining.pop() {
+        if is_prime(&num) {
+            match factors.iter_mut().find(|&&mut (ref prime, _)| *prime == num) {
+                Some(&mut (_, ref mut count)) => *count += 1,
+                None => factors.push((num, 1))
+            }
+        } else {
+            let divisor = pollard_rho(&num, &mut steps, max_steps)?;
+            remaining.push(&num / &divisor);
+            remaining.push(divisor);
+        }
+    }
+
+    factors.sort();
+    Ok(factors)
+}
+/// Formats prime factors as a product, like `2^3 * 3 * 5`
+pub fn format_factors(factors: &[(BigInt, usize)]) -> String {
+    let mut output = String::new();
+    for (i, &(ref prime, count)) in factors.iter().enumerate() {
+        if i != 0 {
+            output.push_str(" * ");
+        }
+        write!(output, "{}", prime).unwrap();
+        if count != 1 {
+            write!(output, "^{}", count).unwrap();
+        }
+    }
+    output
+}
+/// Counts how many numbers from 1 to `num` share no factors with `num`
+pub fn totient(num: &BigInt, max_steps: u64) -> Result<BigInt, CalcError> {
+    let mut result = num.clone();
+    for (prime, _) in factorize(num, max_steps)? {
+        result = &result / &prime * (&prime - BigInt::one());
+    }
+    Ok(result)
+}
+/// Counts how many ways there are to pick `k` items out of `n`.
+/// It takes a step for each number up to k or n - k, whichever is smaller, and gives up after `max_steps`.
+pub fn binomial(n: &BigInt, k: &BigInt, max_steps: u64) -> Result<BigInt, CalcError> {
+    if k.is_negative() || k > n {
+        return Ok(BigInt::zero());
+    }
+    // Fewer steps the closer to the edges k is
+    let k = if k * BigInt::from(2) > *n { n - k } else { k.clone() };
+    let k = match k.to_u64() {
+        Some(k) if k <= max_steps => k as usize,
+        _ => return Err(CalcError::TooManySteps("binomial", max_steps))
+    };
+
+    // The result is (n - k + 1) * ... * n / k!. Dividing as it goes makes ever bigger numbers,
+    // so instead each prime's factors are taken out of the numbers and the extra ones put back.
+    let first = n - BigInt::from(k) + BigInt::one();
+    let mut numbers: Vec<BigInt> = (0..k).map(|i| &first + BigInt::from(i)).collect();
+    let mut composite = vec![false; k + 1];
+    for p in 2..k + 1 {
+        if composite[p] {
+            continue;
+        }
+        for multiple in (p.saturating_mul(p)..k + 1).step_by(p) {
+            composite[multiple] = true;
+        }
+
+        // How many times p divides k!
+        let mut needed = 0;
+        let mut power = p;
+        loop {
+            needed += k / power;
+            power = match power.checked_mul(p) {
+                Some(power) if power <= k => power,
+                _ => break
+            };
+        }
+
+        // Every multiple of p, p^2, ... loses one factor p, and multiples of the biggest power
+        // up to k can have any number of them
+        let prime = BigInt::from(p);
+        let mut removed = 0;
+        let mut power = p;
+        loop {
+            let next = power.checked_mul(p).filter(|&next| next <= k);
+            let start = (-&first).mod_floor(&BigInt::from(power)).to_usize().unwrap();
+            for i in (start..k).step_by(power) {
+                numbers[i] = &numbers[i] / &prime;
+                removed += 1;
+                while next.is_none() && numbers[i].is_multiple_of(&prime) {
+                    numbers[i] = &numbers[i] / &prime;
+                    removed += 1;
+                }
+            }
+            match next {
+                Some(next) => power = next,
+                None => break
+            }
+        }
+        if removed > needed {
+            numbers.push(num::pow(prime, removed - needed));
+        }
+    }
+
+    // Multiply in pairs, so the numbers multiplied together stay about the same size
+    while numbers.len() > 1 {
+        let mut pairs = Vec::with_capacity(numbers.len() / 2 + 1);
+        let mut numbers_iter = numbers.into_iter();
+        while let Some(first) = numbers_iter.next() {
+            pairs.push(match numbers_iter.next() {
+                Some(second) => first * second,
+                None => first
+            });
+        }
+        numbers = pairs;
+    }
+    Ok(numbers.pop().unwrap_or_else(BigInt::one))
+}
+
+/// Applies a bitwise operator to two numbers, treating negatives as two's complement
+/// with as many leading ones as it takes
+pub fn bitwise<F>(num1: &BigInt, num2: &BigInt, op: F) -> BigInt
+    where F: Fn(BigUint, BigUint) -> BigUint
+{
+    // One extra bit so the sign always fits
+    let bits = num1.bits().max(num2.bits()) + 1;
+    let modulus = BigInt::one() << bits;
+    let to_unsigned = |num: &BigInt| num.mod_floor(&modulus).to_biguint().unwrap();
+
+    let result = op(to_unsigned(num1), to_unsigned(num2)).to_bigint().unwrap();
+    if result.bits() == bits {
+        result - modulus
+    } else {
+        result
+    }
+}
+/// Multiplies all the numbers from `from` to `to`, splitting the range in halves
+/// so the numbers multiplied together stay about the same size
+pub fn product(from: u64, to: u64) -> BigInt {
+    match to.saturating_sub(from) {
+        _ if from > to => BigInt::one(),
+        0 => BigInt::from(from),
+        1 => BigInt::from(from) * BigInt::from(to),
+        _ => {
+            let middle = from + (to - from) / 2;
+            product(from, middle) * product(middle + 1, to)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(num: &str) -> BigInt {
+        num.parse().unwrap()
+    }
+
+    #[test]
+    fn primes() {
+        let primes: Vec<u32> = (0..50).filter(|&n| is_prime(&BigInt::from(n))).collect();
+        assert_eq!(primes, [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47]);
+        // Carmichael numbers and strong pseudoprimes to the first few bases
+        assert!(!is_prime(&BigInt::from(561)));
+        assert!(!is_prime(&int("3215031751")));
+        assert!(is_prime(&int("2305843009213693951")));
+        assert!(is_prime(&int("1000000000000000009")));
+        assert!(!is_prime(&int("147573952589676412927")));
+
+        assert_eq!(next_prime(&BigInt::from(-5), 0).unwrap(), BigInt::from(2));
+        assert_eq!(next_prime(&BigInt::from(2), 0).unwrap(), BigInt::from(3));
+        assert_eq!(next_prime(&int("1000000000000"), 1000).unwrap(), int("1000000000039"));
+        assert!(next_prime(&int("1000000000000"), 100).is_err());
+    }
+
+    #[test]
+    fn factors() {
+        let factors = factorize(&BigInt::from(360), 0).unwrap();
+        assert_eq!(format_factors(&factors), "2^3 * 3^2 * 5");
+        assert_eq!(format_factors(&factorize(&BigInt::from(97), 0).unwrap()), "97");
+        assert_eq!(format_factors(&factorize(&int("600851475143"), 1000).unwrap()), "71 * 839 * 1471 * 6857");
+        // Both factors are past trial division, so this needs Pollard's rho
+        let factors = factorize(&int("147573952589676412927"), 1_000_000).unwrap();
+        assert_eq!(format_factors(&factors), "193707721 * 761838257287");
+        assert!(factorize(&int("147573952589676412927"), 100).is_err());
+        assert_eq!(format_factors(&factorize(&int("2000072000198"), 10_000).unwrap()), "2 * 1000003 * 1000033");
+
+        assert_eq!(totient(&BigInt::from(36), 0).unwrap(), BigInt::from(12));
+        assert_eq!(totient(&int("147573952589676412927"), 1_000_000).unwrap(), int("147573951827644447920"));
+    }
+
+    #[test]
+    fn modular() {
+        assert_eq!(modpow(&BigInt::from(2), &int("1000000000000000000"), &int("1000000007")).unwrap(), int("719476260"));
+        assert_eq!(modpow(&BigInt::from(3), &BigInt::from(-1), &BigInt::from(7)).unwrap(), BigInt::from(5));
+        assert!(modpow(&BigInt::from(3), &BigInt::from(2), &BigInt::zero()).is_err());
+        assert_eq!(modinv(&BigInt::from(3), &BigInt::from(7)).unwrap(), BigInt::from(5));
+        assert!(modinv(&BigInt::from(4), &BigInt::from(8)).is_err());
+    }
+
+    #[test]
+    fn counting() {
+        assert_eq!(binomial(&BigInt::from(50), &BigInt::from(25), 100).unwrap(), int("126410606437752"));
+        assert_eq!(binomial(&BigInt::from(5), &BigInt::from(6), 0).unwrap(), BigInt::zero());
+        assert_eq!(binomial(&BigInt::from(5), &BigInt::from(-1), 0).unwrap(), BigInt::zero());
+        assert_eq!(binomial(&BigInt::from(0), &BigInt::from(0), 0).unwrap(), BigInt::one());
+        assert_eq!(binomial(&int("100000000000000000000"), &BigInt::from(2), 2).unwrap(), int("4999999999999999999950000000000000000000"));
+        assert!(binomial(&BigInt::from(50), &BigInt::from(25), 24).is_err());
+        for n in 0..40 {
+            for k in 0..n + 1 {
+                let expected = product(n - k + 1, n) / product(1, k);
+                assert_eq!(binomial(&BigInt::from(n), &BigInt::from(k), 100).unwrap(), expected);
+            }
+        }
+        assert_eq!(product(1, 10), BigInt::from(3628800));
+        assert_eq!(product(5, 4), BigInt::one());
+    }
+
+    #[test]
+    fn bitwise_negatives() {
+        assert_eq!(bitwise(&BigInt::from(-1), &BigInt::from(12), |a, b| a & b), BigInt::from(12));
+        assert_eq!(bitwise(&BigInt::from(-8), &BigInt::from(3), |a, b| a | b), BigInt::from(-5));
+        assert_eq!(bitwise(&BigInt::from(5), &BigInt::from(3), |a, b| a ^ b), BigInt::from(6));
+    }
+}
@@ -2,25 +2,20 @@ extern crate bigdecimal;
 #[macro_use] extern crate failure;
 extern crate num;
 
+pub mod ast;
 pub mod calculator;
+pub mod integer;
+pub mod math;
+pub mod number;
 pub mod parser;
 
-use bigdecimal::BigDecimal;
-use std::collections::HashMap;
+use calculator::{CalcError, Context, Outcome};
 
-/// Calls both `parser::parse` and `calculator::calculate`
+/// Calls `parser::parse`, `ast::parse` and `calculator::run`
 /// and merges the output into one happy Result.
-pub fn parse_and_calc(
-        input: &str,
-        variables: &mut HashMap<String, BigDecimal>,
-        functions: &mut HashMap<String, Vec<parser::Token>>
-    ) -> Result<BigDecimal, calculator::CalcError> {
-
+pub fn parse_and_calc(input: &str, context: &mut Context) -> Result<Outcome, CalcError> {
     parser::parse(input).map_err(|err| err.into()).and_then(|parsed| {
-        calculator::calculate(&mut calculator::Context::new(
-            parsed.into_iter().peekable(),
-            variables,
-            functions
-        ))
+        let expr = ast::parse(parsed.into_iter())?;
+        calculator::run(context, &expr)
     })
 }
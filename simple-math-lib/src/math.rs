@@ -0,0 +1,709 @@
+use bigdecimal::BigDecimal;
+use calculator::CalcError;
+use num::bigint::Sign;
+use num::{self, BigInt, BigRational, Integer, One, Signed, ToPrimitive, Zero};
+use number::{self, Precision};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// How many extra digits to calculate with, to make up for rounding errors on the way
+const GUARD_DIGITS: u64 = 10;
+
+/// Fixed point arithmetic on `BigInt`s scaled by `10^digits`.
+/// This is what all the functions in here calculate with internally.
+struct Fixed {
+    digits: u64,
+    one: BigInt,
+    /// `2^shift / one`, to divide by `one` with a multiplication instead
+    inverse: BigInt,
+    shift: usize
+}
+impl Fixed {
+    fn new(precision: Precision, extra: u64) -> Self {
+        Fixed::with_digits(precision.scale + GUARD_DIGITS + extra)
+    }
+    fn with_digits(digits: u64) -> Self {
+        let one = number::ten_to_the(digits);
+        let shift = one.bits() * 2 + 32;
+        Fixed {
+            digits,
+            inverse: (BigInt::one() << shift) / &one,
+            one,
+            shift
+        }
+    }
+    fn decimal(&self, num: &BigDecimal) -> BigInt {
+        let (int, scale) = num.as_bigint_and_exponent();
+        let shift = self.digits as i64 - scale;
+        if shift >= 0 {
+            int * number::ten_to_the(shift as u64)
+        } else {
+            int / number::ten_to_the(-shift as u64)
+        }
+    }
+    fn int(&self, int: i64) -> BigInt {
+        &self.one * BigInt::from(int)
+    }
+    fn to_decimal(&self, num: &BigInt, precision: Precision) -> BigDecimal {
+        let divisor = number::ten_to_the(self.digits - precision.scale);
+        let rounded = number::round_div(num, &divisor, precision.rounding);
+        number::trim_zeros(BigDecimal::new(rounded, precision.scale as i64))
+    }
+    fn mul(&self, num1: &BigInt, num2: &BigInt) -> BigInt {
+        self.unscale(&(num1 * num2))
+    }
+    /// Calculates `num / one`, rounded towards zero.
+    /// Dividing numbers this big is a lot slower than multiplying them, so this uses Barrett reduction.
+    fn unscale(&self, num: &BigInt) -> BigInt {
+        let abs = num.abs();
+        if abs.bits() > self.shift {
+            return num / &self.one;
+        }
+        // The estimate is at most a couple too small
+        let mut quotient = (&abs * &self.inverse) >> self.shift;
+        let mut remainder = abs - &quotient * &self.one;
+        while remainder >= self.one {
+            quotient = quotient + BigInt::one();
+            remainder = remainder - &self.one;
+        }
+        if num.is_negative() { -quotient } else { quotient }
+    }
+    fn div(&self, num1: &BigInt, num2: &BigInt) -> BigInt {
+        num1 * &self.one / num2
+    }
+
+    fn sqrt(&self, num: &BigInt) -> BigInt {
+        isqrt(&(num * &self.one))
+    }
+    fn exp(&self, num: &BigInt) -> BigInt {
+        if num.is_negative() {
+            // e^-x is below the last digit once x is more than digits * ln(10)
+            if -num > self.int(self.digits as i64 * 231 / 100 + 1) {
+                return BigInt::zero();
+            }
+            let exp = self.exp(&-num);
+            return self.div(&self.one, &exp);
+        }
+
+        // Halve the input well below 1 so the series converges quickly, then square it back up.
+        // Each halving costs a squaring, so the most work is saved around sqrt(bits) of them.
+        let bits = num.bits().saturating_sub(self.one.bits()) + 1;
+        let halvings = bits + (self.digits as f64 * 3.33).sqrt() as usize / 2;
+        // Every squaring doubles the error, so calculate with enough extra digits to make up for it
+        let extra = halvings as u64 * 31 / 100 + 1;
+        let inner = Fixed::with_digits(self.digits + extra);
+        let num = (num * number::ten_to_the(extra)) >> halvings;
+
+        let mut sum = inner.one.clone();
+        let mut term = inner.one.clone();
+        let mut i = 1;
+        loop {
+            term = inner.mul(&term, &num) / BigInt::from(i);
+            if term.is_zero() {
+                break;
+            }
+            sum = sum + &term;
+            i += 1;
+        }
+
+        for _ in 0..halvings {
+            sum = inner.mul(&sum, &sum);
+        }
+        sum / number::ten_to_the(extra)
+    }
+    /// Calculates `2 * atanh(num)`, which is `ln((1 + num) / (1 - num))`
+    fn atanh2(&self, num: &BigInt) -> BigInt {
+        let square = self.mul(num, num);
+        let mut sum = BigInt::zero();
+        let mut term = num.clone();
+        let mut i = 1;
+        while !term.is_zero() {
+            sum = sum + &term / BigInt::from(i);
+            term = self.mul(&term, &square);
+            i += 2;
+        }
+        sum * BigInt::from(2)
+    }
+    fn ln2(&self) -> BigInt {
+        self.atanh2(&(&self.one / BigInt::from(3)))
+    }
+    fn ln(&self, num: &BigInt) -> BigInt {
+        // Write the input as m * 2^k where 1 <= m < 2
+        let two = &self.one * BigInt::from(2);
+        let mut k = num.bits() as i64 - self.one.bits() as i64;
+        let mut m = if k >= 0 { num >> k as usize } else { num << -k as usize };
+        while m >= two {
+            m = m >> 1;
+            k += 1;
+        }
+        while m < self.one {
+            m = m << 1;
+            k -= 1;
+        }
+
+        let z = self.div(&(&m - &self.one), &(&m + &self.one));
+        self.atanh2(&z) + self.ln2() * BigInt::from(k)
+    }
+    /// Calculates the natural logarithm of a positive decimal.
+    /// Inputs too small to show up in the fixed point digits are still calculated right.
+    fn ln_decimal(&self, num: &BigDecimal) -> BigInt {
+        // ln(int * 10^-scale) = ln(int) - scale * ln(10)
+        let (int, scale) = num.as_bigint_and_exponent();
+        let ln = self.ln(&(int * &self.one));
+        if scale == 0 {
+            return ln;
+        }
+        ln - self.ln(&self.int(10)) * BigInt::from(scale)
+    }
+    /// Calculates `atan(1 / n)`
+    fn atan_inv(&self, n: i64) -> BigInt {
+        let square = BigInt::from(n * n);
+        let mut sum = BigInt::zero();
+        let mut term = &self.one / BigInt::from(n);
+        let mut i = 1;
+        while !term.is_zero() {
+            if i % 4 == 1 {
+                sum = sum + &term / BigInt::from(i);
+            } else {
+                sum = sum - &term / BigInt::from(i);
+            }
+            term = term / &square;
+            i += 2;
+        }
+        sum
+    }
+    fn pi(&self) -> BigInt {
+        // Machin's formula
+        (self.atan_inv(5) * BigInt::from(4) - self.atan_inv(239)) * BigInt::from(4)
+    }
+    fn atan(&self, num: &BigInt) -> BigInt {
+        if num.is_negative() {
+            return -self.atan(&-num);
+        }
+        if *num > self.one {
+            return self.pi() / BigInt::from(2) - self.atan(&self.div(&self.one, num));
+        }
+
+        // atan(x) = 2 * atan(x / (1 + sqrt(1 + x^2))), which shrinks x so the series converges quickly
+        let mut num = num.clone();
+        let mut doublings = 0;
+        while &num * BigInt::from(10) > self.one {
+            let root = self.sqrt(&(&self.one + self.mul(&num, &num)));
+            num = self.div(&num, &(&self.one + root));
+            doublings += 1;
+        }
+
+        let square = self.mul(&num, &num);
+        let mut sum = BigInt::zero();
+        let mut term = num;
+        let mut i = 1;
+        while !term.is_zero() {
+            if i % 4 == 1 {
+                sum = sum + &term / BigInt::from(i);
+            } else {
+                sum = sum - &term / BigInt::from(i);
+            }
+            term = self.mul(&term, &square);
+            i += 2;
+        }
+        sum << doublings
+    }
+    /// Calculates `ln(gamma(num))` for positive `num`, using Stirling's series
+    fn ln_gamma(&self, num: &BigInt) -> BigInt {
+        // The series only converges quickly far enough from zero, so move the input up first
+        // using gamma(x) = gamma(x + n) / (x * (x + 1) * ... * (x + n - 1))
+        let target = self.int(self.digits as i64);
+        let mut z = num.clone();
+        let mut shift = BigInt::zero();
+        if z < target {
+            // x on its own, so tiny inputs don't lose digits in the product
+            shift = self.ln(&z);
+            z = z + &self.one;
+            let mut product = self.one.clone();
+            while z < target {
+                product = self.mul(&product, &z);
+                z = z + &self.one;
+            }
+            shift = shift + self.ln(&product);
+        }
+
+        let half = &self.one / BigInt::from(2);
+        let two_pi = self.pi() * BigInt::from(2);
+        let mut sum = self.mul(&(&z - half), &self.ln(&z)) - &z + self.ln(&two_pi) / BigInt::from(2);
+
+        let square = self.mul(&z, &z);
+        let mut power = z;
+        for (i, bernoulli) in bernoulli(self.digits as usize / 2 + 10).into_iter().enumerate() {
+            // B_2k / (2k * (2k - 1) * z^(2k - 1))
+            let k = BigInt::from(2 * (i + 1));
+            let coefficient = bernoulli.numer() * &self.one / (bernoulli.denom() * (&k - BigInt::one()) * k);
+            let term = self.div(&coefficient, &power);
+            if term.is_zero() {
+                break;
+            }
+            sum = sum + term;
+            power = self.mul(&power, &square);
+        }
+        sum - shift
+    }
+    fn sin_cos(&self, num: &BigInt) -> (BigInt, BigInt) {
+        // Move the input into [-pi, pi]
+        let two_pi = self.pi() * BigInt::from(2);
+        let turns = number::round_div(num, &two_pi, number::Rounding::HalfUp);
+        let num = num - turns * two_pi;
+
+        // Like in exp, halve the input so the series converges quickly, then double the angle back up
+        let halvings = (self.digits as f64 * 3.33).sqrt() as usize / 2;
+        let extra = halvings as u64 * 31 / 100 + 1;
+        let inner = Fixed::with_digits(self.digits + extra);
+        let num = (num * number::ten_to_the(extra)) >> halvings;
+
+        let mut sin = BigInt::zero();
+        let mut cos = BigInt::zero();
+        let mut term = inner.one.clone();
+        let mut i: i64 = 0;
+        while !term.is_zero() {
+            match i % 4 {
+                0 => cos = cos + &term,
+                1 => sin = sin + &term,
+                2 => cos = cos - &term,
+                _ => sin = sin - &term
+            }
+            i += 1;
+            term = inner.mul(&term, &num) / BigInt::from(i);
+        }
+        // sin(2x) = 2 * sin(x) * cos(x), and cos(2x) = 1 - 2 * sin(x)^2
+        let two = BigInt::from(2);
+        for _ in 0..halvings {
+            let double_sin = inner.mul(&sin, &cos) * &two;
+            cos = &inner.one - inner.mul(&sin, &sin) * &two;
+            sin = double_sin;
+        }
+        let scale = number::ten_to_the(extra);
+        (sin / &scale, cos / scale)
+    }
+}
+
+/// Calculates the Bernoulli numbers B_2, B_4, ..., B_2n, from the tangent numbers
+fn bernoulli(n: usize) -> Vec<BigRational> {
+    let mut tangent = vec![BigInt::zero(); n + 1];
+    if n > 0 {
+        tangent[1] = BigInt::one();
+    }
+    for k in 2..n + 1 {
+        tangent[k] = &tangent[k - 1] * BigInt::from(k - 1);
+    }
+    for k in 2..n + 1 {
+        for j in k..n + 1 {
+            tangent[j] = &tangent[j - 1] * BigInt::from(j - k) + &tangent[j] * BigInt::from(j - k + 2);
+        }
+    }
+    (1..n + 1).map(|k| {
+        let four_to_the_k = BigInt::one() << (2 * k);
+        let numer = BigInt::from(2 * k) * &tangent[k];
+        let bernoulli = BigRational::new(numer, &four_to_the_k * (&four_to_the_k - BigInt::one()));
+        if k.is_even() { -bernoulli } else { bernoulli }
+    }).collect()
+}
+
+/// Calculates the whole part of the square root of the positive number `num`
+pub fn isqrt(num: &BigInt) -> BigInt {
+    iroot(num, 2)
+}
+/// Calculates the whole part of the `n`th root of the positive number `num`
+pub fn iroot(num: &BigInt, n: usize) -> BigInt {
+    if num.is_zero() || n == 1 {
+        return num.clone();
+    }
+
+    // Newton's method, starting above the root and walking down
+    let n_int = BigInt::from(n);
+    let mut x = BigInt::one() << (num.bits() / n + 1);
+    loop {
+        let y = (&x * BigInt::from(n - 1) + num / num::pow(x.clone(), n - 1)) / &n_int;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+/// How many digits there are before the decimal point
+fn int_digits(num: &BigDecimal) -> u64 {
+    use num::bigint::ToBigInt;
+    num.to_bigint().unwrap().abs().to_str_radix(10).len() as u64
+}
+/// How many digits there are after the decimal point
+fn frac_digits(num: &BigDecimal) -> u64 {
+    let (_, scale) = num.as_bigint_and_exponent();
+    if scale > 0 { scale as u64 } else { 0 }
+}
+
+/// Roughly calculates log10 of `num`
+fn log10(num: &BigDecimal) -> f64 {
+    let (int, scale) = num.as_bigint_and_exponent();
+    number::log10(&int) - scale as f64
+}
+/// Roughly converts `num` to an `f64`, which is infinite if it's too big to fit
+fn approximate(num: &BigDecimal) -> f64 {
+    let abs = 10f64.powf(log10(num));
+    if num.is_negative() { -abs } else { abs }
+}
+
+/// The names of the constants `constant` knows
+pub const CONSTANTS: [&str; 6] = ["e", "ln2", "phi", "pi", "sqrt2", "tau"];
+
+thread_local! {
+    /// The constants calculated so far, with the precision they were calculated for
+    static CONSTANT_CACHE: RefCell<HashMap<(&'static str, Precision), BigDecimal>> = RefCell::new(HashMap::new());
+}
+
+/// Calculates the mathematical constant called `name`, if there is one
+pub fn constant(name: &str, precision: Precision) -> Option<BigDecimal> {
+    // Every variable lookup checks for a constant first, so other names must be cheap to turn down
+    let name = *CONSTANTS.iter().find(|&&constant| constant == name)?;
+    let cached = CONSTANT_CACHE.with(|cache| cache.borrow().get(&(name, precision)).cloned());
+    if cached.is_some() {
+        return cached;
+    }
+
+    let fixed = Fixed::new(precision, 0);
+    let result = match name {
+        "e" => fixed.exp(&fixed.one),
+        "ln2" => fixed.ln2(),
+        // (1 + sqrt(5)) / 2
+        "phi" => (&fixed.one + fixed.sqrt(&fixed.int(5))) / BigInt::from(2),
+        "pi" => fixed.pi(),
+        "sqrt2" => fixed.sqrt(&fixed.int(2)),
+        "tau" => fixed.pi() * BigInt::from(2),
+        _ => unreachable!()
+    };
+    let result = fixed.to_decimal(&result, precision);
+    CONSTANT_CACHE.with(|cache| cache.borrow_mut().insert((name, precision), result.clone()));
+    Some(result)
+}
+
+/// Calculates the square root of `num`
+pub fn sqrt(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if num.is_negative() {
+        return Err(CalcError::OutOfDomain("sqrt"));
+    }
+    let fixed = Fixed::new(precision, 0);
+    Ok(fixed.to_decimal(&fixed.sqrt(&fixed.decimal(num)), precision))
+}
+/// Calculates the `n`th root of `num`
+pub fn root(num: &BigDecimal, n: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    use num::bigint::ToBigInt;
+    if n.is_zero() {
+        return Err(CalcError::OutOfDomain("root"));
+    }
+    if num.is_negative() {
+        // Only odd whole roots of negative numbers are real, and they're the negated roots
+        if n.with_scale(0) != *n || n.to_bigint().unwrap().is_even() {
+            return Err(CalcError::OutOfDomain("root"));
+        }
+        return root(&-num, n, precision).map(|root| -root);
+    }
+    if num.is_zero() {
+        return if n.is_negative() { Err(CalcError::DivideByZero) } else { Ok(BigDecimal::zero()) };
+    }
+
+    // exp(ln(num) / n), which has about log10(num) / n digits before the point that also need to be right.
+    // Dividing by n needs all of its digits after the point.
+    let size = log10(num) / approximate(n);
+    if size >= u64::MAX as f64 {
+        return Err(CalcError::TooManyDigits("root", u64::MAX));
+    }
+    let extra = if size > 0.0 { size as u64 + 1 } else { 0 } + frac_digits(n);
+    let fixed = Fixed::new(precision, extra);
+    let ln = fixed.ln_decimal(num);
+    Ok(fixed.to_decimal(&fixed.exp(&fixed.div(&ln, &fixed.decimal(n))), precision))
+}
+/// Calculates e to the power of `num`
+pub fn exp(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    // The result has about num * log10(e) digits before the point that also need to be right
+    let extra = if num.is_positive() { num.to_u64().unwrap_or(0) / 2 + 1 } else { 0 };
+    let fixed = Fixed::new(precision, extra);
+    Ok(fixed.to_decimal(&fixed.exp(&fixed.decimal(num)), precision))
+}
+/// Calculates the positive number `num` to the power of `power`
+pub fn pow(num: &BigDecimal, power: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if !num.is_positive() {
+        return Err(CalcError::OutOfDomain("pow"));
+    }
+    // The result has about power * log10(num) digits before the point that also need to be right,
+    // and multiplying by the power blows up any error in ln(num)
+    let size = approximate(power) * log10(num);
+    if size >= u64::MAX as f64 {
+        return Err(CalcError::TooManyDigits("pow", u64::MAX));
+    }
+    let extra = if size > 0.0 { size as u64 + 1 } else { 0 } + int_digits(power);
+    let fixed = Fixed::new(precision, extra);
+    let ln = fixed.ln_decimal(num);
+    Ok(fixed.to_decimal(&fixed.exp(&fixed.mul(&ln, &fixed.decimal(power))), precision))
+}
+/// Calculates the natural logarithm of `num`
+pub fn ln(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    match num.sign() {
+        Sign::Minus => return Err(CalcError::OutOfDomain("ln")),
+        Sign::NoSign => return Err(CalcError::Infinite("ln")),
+        Sign::Plus => ()
+    }
+    let fixed = Fixed::new(precision, 0);
+    Ok(fixed.to_decimal(&fixed.ln_decimal(num), precision))
+}
+/// Calculates the logarithm of `num` in base `base`
+pub fn log(num: &BigDecimal, base: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if !base.is_positive() || *base == BigDecimal::one() {
+        return Err(CalcError::OutOfDomain("log"));
+    }
+    match num.sign() {
+        Sign::Minus => return Err(CalcError::OutOfDomain("log")),
+        Sign::NoSign => return Err(CalcError::Infinite("log")),
+        Sign::Plus => ()
+    }
+    // ln(base) is tiny when base is close to 1, which blows up any error in ln(num).
+    // It can't be closer to 1 than its last digit though.
+    let fixed = Fixed::new(precision, frac_digits(base) + 1);
+    let ln_base = fixed.ln_decimal(base);
+    Ok(fixed.to_decimal(&fixed.div(&fixed.ln_decimal(num), &ln_base), precision))
+}
+/// Calculates the sine of `num`, in radians
+pub fn sin(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, int_digits(num));
+    Ok(fixed.to_decimal(&fixed.sin_cos(&fixed.decimal(num)).0, precision))
+}
+/// Calculates the cosine of `num`, in radians
+pub fn cos(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, int_digits(num));
+    Ok(fixed.to_decimal(&fixed.sin_cos(&fixed.decimal(num)).1, precision))
+}
+/// Calculates the tangent of `num`, in radians
+pub fn tan(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, int_digits(num));
+    let (sin, cos) = fixed.sin_cos(&fixed.decimal(num));
+    if cos.is_zero() {
+        return Err(CalcError::Infinite("tan"));
+    }
+    Ok(fixed.to_decimal(&fixed.div(&sin, &cos), precision))
+}
+/// Calculates the inverse sine of `num`, in radians
+pub fn asin(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if num.abs() > BigDecimal::one() {
+        return Err(CalcError::OutOfDomain("asin"));
+    }
+    // Close to 1 or -1 the square root in fixed_asin loses digits
+    let fixed = Fixed::new(precision, frac_digits(num));
+    Ok(fixed.to_decimal(&fixed_asin(&fixed, &fixed.decimal(num)), precision))
+}
+/// Calculates the inverse cosine of `num`, in radians
+pub fn acos(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if num.abs() > BigDecimal::one() {
+        return Err(CalcError::OutOfDomain("acos"));
+    }
+    let fixed = Fixed::new(precision, frac_digits(num));
+    let asin = fixed_asin(&fixed, &fixed.decimal(num));
+    Ok(fixed.to_decimal(&(fixed.pi() / BigInt::from(2) - asin), precision))
+}
+fn fixed_asin(fixed: &Fixed, num: &BigInt) -> BigInt {
+    if num.abs() == fixed.one {
+        let half_pi = fixed.pi() / BigInt::from(2);
+        return if num.is_negative() { -half_pi } else { half_pi };
+    }
+    // asin(x) = atan(x / sqrt(1 - x^2))
+    let root = fixed.sqrt(&(&fixed.one - fixed.mul(num, num)));
+    fixed.atan(&fixed.div(num, &root))
+}
+/// Calculates the inverse tangent of `num`, in radians
+pub fn atan(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, 0);
+    Ok(fixed.to_decimal(&fixed.atan(&fixed.decimal(num)), precision))
+}
+/// Calculates the angle of the point (`x`, `y`), in radians
+pub fn atan2(y: &BigDecimal, x: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, int_digits(x) + int_digits(y));
+    let fixed_x = fixed.decimal(x);
+    let fixed_y = fixed.decimal(y);
+    let half_pi = fixed.pi() / BigInt::from(2);
+
+    let result = match (fixed_x.sign(), fixed_y.sign()) {
+        (Sign::NoSign, Sign::NoSign) => BigInt::zero(),
+        (Sign::NoSign, Sign::Plus) => half_pi,
+        (Sign::NoSign, Sign::Minus) => -half_pi,
+        (Sign::Plus, _) => fixed.atan(&fixed.div(&fixed_y, &fixed_x)),
+        (Sign::Minus, Sign::Minus) => fixed.atan(&fixed.div(&fixed_y, &fixed_x)) - fixed.pi(),
+        (Sign::Minus, _) => fixed.atan(&fixed.div(&fixed_y, &fixed_x)) + fixed.pi()
+    };
+    Ok(fixed.to_decimal(&result, precision))
+}
+/// Calculates the hyperbolic sine of `num`
+pub fn sinh(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    // sinh is odd, and e^x is more precise than e^-x for big inputs
+    let fixed = Fixed::new(precision, num.abs().to_u64().unwrap_or(0) / 2 + 1);
+    let exp = fixed.exp(&fixed.decimal(&num.abs()));
+    let inverse = fixed.div(&fixed.one, &exp);
+    let result = (exp - inverse) / BigInt::from(2);
+    Ok(fixed.to_decimal(&if num.is_negative() { -result } else { result }, precision))
+}
+/// Calculates the hyperbolic cosine of `num`
+pub fn cosh(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, num.abs().to_u64().unwrap_or(0) / 2 + 1);
+    let exp = fixed.exp(&fixed.decimal(&num.abs()));
+    let inverse = fixed.div(&fixed.one, &exp);
+    Ok(fixed.to_decimal(&((exp + inverse) / BigInt::from(2)), precision))
+}
+/// Calculates the hyperbolic tangent of `num`
+pub fn tanh(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    let fixed = Fixed::new(precision, 0);
+    // Far enough from zero, the result is 1 or -1 to any digit we care about
+    if num.abs() > BigDecimal::from(fixed.digits) {
+        let one = fixed.int(if num.is_negative() { -1 } else { 1 });
+        return Ok(fixed.to_decimal(&one, precision));
+    }
+    // tanh(x) = (e^2x - 1) / (e^2x + 1)
+    let exp = fixed.exp(&(fixed.decimal(num) * BigInt::from(2)));
+    Ok(fixed.to_decimal(&fixed.div(&(&exp - &fixed.one), &(&exp + &fixed.one)), precision))
+}
+/// Calculates the gamma function of `num`, which is `(num - 1)!` for whole numbers
+pub fn gamma(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if !num.is_positive() && num.with_scale(0) == *num {
+        return Err(CalcError::Infinite("gamma"));
+    }
+    // The result has about x * log10(x) digits before the point that also need to be right
+    let size = approximate(num);
+    let digits = if size > 1.0 { size * size.log10() } else { 0.0 };
+    if digits >= u64::MAX as f64 {
+        return Err(CalcError::TooManyDigits("gamma", u64::MAX));
+    }
+    let extra = digits as u64 + 1;
+    let fixed = Fixed::new(precision, extra + int_digits(num) + frac_digits(num));
+    let x = fixed.decimal(num);
+
+    let result = if x.is_positive() {
+        fixed.exp(&fixed.ln_gamma(&x))
+    } else {
+        // gamma(x) = pi / (sin(pi * x) * gamma(1 - x)), where gamma(1 - x) can be far too big
+        // to calculate while the result is tiny, so this goes through the logarithms instead
+        let pi = fixed.pi();
+        let (sin, _) = fixed.sin_cos(&fixed.mul(&pi, &x));
+        let ln = fixed.ln(&pi) - fixed.ln(&sin.abs()) - fixed.ln_gamma(&(&fixed.one - &x));
+        let result = fixed.exp(&ln);
+        if sin.is_negative() { -result } else { result }
+    };
+    Ok(fixed.to_decimal(&result, precision))
+}
+/// Calculates the natural logarithm of the absolute value of the gamma function of `num`
+pub fn lgamma(num: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if !num.is_positive() && num.with_scale(0) == *num {
+        return Err(CalcError::Infinite("lgamma"));
+    }
+    let fixed = Fixed::new(precision, int_digits(num) + frac_digits(num));
+    let x = fixed.decimal(num);
+
+    let result = if x.is_positive() {
+        fixed.ln_gamma(&x)
+    } else {
+        // ln|gamma(x)| = ln(pi) - ln|sin(pi * x)| - ln(gamma(1 - x))
+        let pi = fixed.pi();
+        let (sin, _) = fixed.sin_cos(&fixed.mul(&pi, &x));
+        fixed.ln(&pi) - fixed.ln(&sin.abs()) - fixed.ln_gamma(&(&fixed.one - &x))
+    };
+    Ok(fixed.to_decimal(&result, precision))
+}
+/// Calculates the beta function of the positive numbers `a` and `b`
+pub fn beta(a: &BigDecimal, b: &BigDecimal, precision: Precision) -> Result<BigDecimal, CalcError> {
+    if !a.is_positive() || !b.is_positive() {
+        return Err(CalcError::OutOfDomain("beta"));
+    }
+    let extra = int_digits(a) + int_digits(b) + frac_digits(a) + frac_digits(b);
+    let fixed = Fixed::new(precision, extra);
+    let a = fixed.decimal(a);
+    let b = fixed.decimal(b);
+    // beta(a, b) = gamma(a) * gamma(b) / gamma(a + b)
+    let ln = fixed.ln_gamma(&a) + fixed.ln_gamma(&b) - fixed.ln_gamma(&(&a + &b));
+    Ok(fixed.to_decimal(&fixed.exp(&ln), precision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use number::Rounding;
+
+    const PRECISION: Precision = Precision {
+        scale: 30,
+        rounding: Rounding::Truncate
+    };
+
+    fn dec(num: &str) -> BigDecimal {
+        num.parse().unwrap()
+    }
+    /// Checks that `result` matches the first 28 decimals of `expected`
+    fn assert_close(result: Result<BigDecimal, CalcError>, expected: &str) {
+        let result = result.unwrap();
+        let error = (&result - dec(expected)).abs();
+        assert!(error < dec("1e-28"), "got {}, expected {}", result, expected);
+    }
+
+    #[test]
+    fn constants() {
+        assert_close(Ok(constant("pi", PRECISION).unwrap()), "3.141592653589793238462643383279502884197");
+        assert_close(Ok(constant("e", PRECISION).unwrap()), "2.718281828459045235360287471352662497757");
+        assert_close(Ok(constant("ln2", PRECISION).unwrap()), "0.6931471805599453094172321214581765680755");
+        assert_close(Ok(constant("sqrt2", PRECISION).unwrap()), "1.41421356237309504880168872420969807857");
+        assert!(constant("nope", PRECISION).is_none());
+    }
+
+    #[test]
+    fn exp_and_ln() {
+        assert_close(exp(&dec("-1"), PRECISION), "0.3678794411714423215955237701614608674458");
+        assert_close(exp(&dec("10"), PRECISION), "22026.46579480671651695790064528424436635");
+        assert_close(exp(&dec("-1e30"), PRECISION), "0");
+        assert_close(ln(&dec("2"), PRECISION), "0.6931471805599453094172321214581765680755");
+        assert_close(ln(&dec("1e-1000"), PRECISION), "-2302.585092994045684017991454684364207601");
+        assert_close(log(&dec("8"), &dec("2"), PRECISION), "3");
+        assert_close(log(&dec("1e-1000"), &dec("10"), PRECISION), "-1000");
+        assert!(ln(&dec("0"), PRECISION).is_err());
+        assert!(ln(&dec("-1"), PRECISION).is_err());
+    }
+
+    #[test]
+    fn powers_and_roots() {
+        assert_close(pow(&dec("2"), &dec("0.5"), PRECISION), "1.41421356237309504880168872420969807857");
+        assert_close(pow(&dec("1e-1000"), &dec("0.5"), PRECISION), "0");
+        assert_close(sqrt(&dec("2"), PRECISION), "1.41421356237309504880168872420969807857");
+        assert_close(root(&dec("27"), &dec("3"), PRECISION), "3");
+        assert_close(root(&dec("-8"), &dec("3"), PRECISION), "-2");
+        assert_close(root(&dec("2"), &dec("3000"), PRECISION), "1.0002310757540765814793668850788927439730");
+        assert_eq!(isqrt(&BigInt::from(99)), BigInt::from(9));
+        assert_eq!(iroot(&BigInt::from(1000), 3), BigInt::from(10));
+        assert_eq!(iroot(&BigInt::from(999), 3), BigInt::from(9));
+    }
+
+    #[test]
+    fn trigonometry() {
+        assert_close(sin(&dec("1"), PRECISION), "0.8414709848078965066525023216302989996226");
+        assert_close(cos(&dec("1"), PRECISION), "0.5403023058681397174009366074429766037323");
+        assert_close(atan(&dec("1"), PRECISION), "0.7853981633974483096156608458198757210493");
+        assert_close(tanh(&dec("0.5"), PRECISION), "0.4621171572600097585023184836436725487303");
+    }
+
+    #[test]
+    fn bernoulli_numbers() {
+        let expected = [(1, 6), (-1, 30), (1, 42), (-1, 30), (5, 66)];
+        let numbers = bernoulli(expected.len());
+        for (number, &(numer, denom)) in numbers.iter().zip(expected.iter()) {
+            assert_eq!(*number, BigRational::new(BigInt::from(numer), BigInt::from(denom)));
+        }
+    }
+
+    #[test]
+    fn gamma_function() {
+        assert_close(gamma(&dec("5"), PRECISION), "24");
+        assert_close(gamma(&dec("0.5"), PRECISION), "1.772453850905516027298167483341145182798");
+        assert_close(gamma(&dec("-0.5"), PRECISION), "-3.544907701811032054596334966682290365595");
+        assert_close(gamma(&dec("-10.5"), PRECISION), "-0.0000002640121820547716316246385325311240439682");
+        assert_close(lgamma(&dec("100"), PRECISION), "359.1342053695753987760440104602869096126");
+        assert!(gamma(&dec("-2"), PRECISION).is_err());
+        assert!(gamma(&dec("1e400"), PRECISION).is_err());
+    }
+}
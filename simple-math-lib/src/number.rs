@@ -0,0 +1,516 @@
+use bigdecimal::BigDecimal;
+use calculator::CalcError;
+use math;
+use num::bigint::{Sign, ToBigInt};
+use num::{self, BigInt, BigRational, Integer, One, Signed, ToPrimitive, Zero};
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// Which numeric backend to calculate with
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Mode {
+    /// Decimal numbers. Divisions that don't terminate get cut off.
+    #[default]
+    Decimal,
+    /// Exact fractions. `+ - * / %` and whole powers never lose precision.
+    Rational
+}
+
+/// How to get rid of the digits that don't fit
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Rounding {
+    /// Round to the nearest number, and away from zero when in the middle
+    HalfUp,
+    /// Round to the nearest number, and to the even neighbour when in the middle
+    HalfEven,
+    /// Round towards negative infinity
+    Floor,
+    /// Round towards positive infinity
+    Ceiling,
+    /// Round towards zero
+    Truncate
+}
+
+/// How many digits to keep after the decimal point when a result can't be exact
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Precision {
+    pub scale: u64,
+    pub rounding: Rounding
+}
+impl Default for Precision {
+    fn default() -> Self {
+        Precision {
+            scale: 100,
+            rounding: Rounding::Truncate
+        }
+    }
+}
+
+/// A fixed integer size that results of operators wrap around to, like in C
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Width {
+    pub bits: usize,
+    pub signed: bool,
+    /// Whether a result that doesn't fit is an error instead of wrapping around
+    pub strict: bool
+}
+impl Width {
+    /// Returns the number as this width would see it: fractions are cut off, and anything
+    /// out of range wraps around or is an error, depending on `strict`
+    pub fn fit(&self, num: &Number, mode: Mode) -> Result<Number, CalcError> {
+        let int = num.round(0, Rounding::Truncate).to_bigint().unwrap();
+        let wrapped = self.wrap(&int);
+        if self.strict && wrapped != int {
+            return Err(CalcError::Overflow(*self));
+        }
+        Ok(Number::from_bigint(wrapped, mode))
+    }
+    /// Wraps a whole number around into the range of this width
+    pub fn wrap(&self, int: &BigInt) -> BigInt {
+        let modulus = BigInt::one() << self.bits;
+        let unsigned = int.mod_floor(&modulus);
+        if self.signed && unsigned.bits() == self.bits {
+            unsigned - modulus
+        } else {
+            unsigned
+        }
+    }
+    /// Returns the bit pattern of a whole number in this width, as an unsigned number
+    pub fn to_unsigned(&self, int: &BigInt) -> BigInt {
+        int.mod_floor(&(BigInt::one() << self.bits))
+    }
+}
+impl fmt::Display for Width {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.signed { 'i' } else { 'u' }, self.bits)
+    }
+}
+
+/// A number, either a decimal or an exact fraction
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Number {
+    Decimal(BigDecimal),
+    Rational(BigRational)
+}
+
+/// Returns 10 to the power of `exp`
+pub fn ten_to_the(exp: u64) -> BigInt {
+    num::pow(BigInt::from(10), exp as usize)
+}
+
+impl Number {
+    pub fn zero() -> Self {
+        Number::Decimal(BigDecimal::zero())
+    }
+    pub fn one() -> Self {
+        Number::Decimal(BigDecimal::one())
+    }
+    /// Returns a whole number in the representation used by `mode`
+    pub fn from_bigint(int: BigInt, mode: Mode) -> Self {
+        match mode {
+            Mode::Decimal => Number::Decimal(BigDecimal::new(int, 0)),
+            Mode::Rational => Number::Rational(BigRational::from_integer(int))
+        }
+    }
+    /// Converts the number to the representation used by `mode`
+    pub fn into_mode(self, mode: Mode, precision: Precision) -> Self {
+        match (self, mode) {
+            (Number::Decimal(num), Mode::Rational) => Number::Rational(decimal_to_rational(&num)),
+            (Number::Rational(num), Mode::Decimal) => Number::Decimal(rational_to_decimal(&num, precision)),
+            (num, _) => num
+        }
+    }
+    /// Returns the number as a decimal, rounding fractions that don't terminate
+    pub fn to_decimal(&self, precision: Precision) -> BigDecimal {
+        match *self {
+            Number::Decimal(ref num) => num.clone(),
+            Number::Rational(ref num) => rational_to_decimal(num, precision)
+        }
+    }
+    /// Returns the number as an exact fraction
+    pub fn to_rational(&self) -> BigRational {
+        match *self {
+            Number::Decimal(ref num) => decimal_to_rational(num),
+            Number::Rational(ref num) => num.clone()
+        }
+    }
+    /// Returns the number as a decimal with exactly `places` digits after the point
+    pub fn to_decimal_places(&self, places: u64, rounding: Rounding) -> BigDecimal {
+        let num = self.to_rational();
+        let numer = num.numer() * ten_to_the(places);
+        BigDecimal::new(round_div(&numer, num.denom(), rounding), places as i64)
+    }
+    /// Rounds the number to `places` digits after the decimal point.
+    /// Negative places round to the left of the decimal point instead.
+    pub fn round(&self, places: i64, rounding: Rounding) -> Number {
+        if places >= 0 && self.scale().is_some_and(|scale| scale <= places as u64) {
+            return self.clone();
+        }
+        let num = self.to_rational();
+        let shift = ten_to_the(places.unsigned_abs());
+        let (numer, denom) = if places >= 0 {
+            (num.numer() * &shift, num.denom().clone())
+        } else {
+            (num.numer().clone(), num.denom() * &shift)
+        };
+        let rounded = round_div(&numer, &denom, rounding);
+        match *self {
+            Number::Decimal(_) if places >= 0 => Number::Decimal(trim_zeros(BigDecimal::new(rounded, places))),
+            Number::Decimal(_) => Number::Decimal(BigDecimal::new(rounded * shift, 0)),
+            Number::Rational(_) if places >= 0 => Number::Rational(BigRational::new(rounded, shift)),
+            Number::Rational(_) => Number::Rational(BigRational::from_integer(rounded * shift))
+        }
+    }
+    /// Returns the number as a decimal, if it can be written as one with a finite amount of digits
+    pub fn to_exact_decimal(&self) -> Option<BigDecimal> {
+        match *self {
+            Number::Decimal(ref num) => Some(num.clone()),
+            Number::Rational(ref num) => {
+                // Only fractions whose denominator is made up of 2s and 5s terminate
+                let mut denom = num.denom().clone();
+                let mut places = 0;
+                let ten = BigInt::from(10);
+                while !denom.is_one() {
+                    let (quotient, remainder) = denom.div_rem(&ten);
+                    if remainder.is_zero() {
+                        denom = quotient;
+                    } else if denom.is_even() {
+                        denom = denom / BigInt::from(2);
+                    } else if denom.is_multiple_of(&BigInt::from(5)) {
+                        denom = denom / BigInt::from(5);
+                    } else {
+                        return None;
+                    }
+                    places += 1;
+                }
+                Some(BigDecimal::new(num.numer() * ten_to_the(places) / num.denom(), places as i64))
+            }
+        }
+    }
+    /// How many digits there are after the decimal point,
+    /// if the number can be written as a decimal with a finite amount of digits
+    pub fn scale(&self) -> Option<u64> {
+        let (_, scale) = trim_zeros(self.to_exact_decimal()?).into_bigint_and_exponent();
+        Some(scale.max(0) as u64)
+    }
+    /// Compares the values of two numbers, regardless of how they're represented
+    pub fn compare(&self, other: &Number) -> Ordering {
+        match (self, other) {
+            (Number::Decimal(num1), Number::Decimal(num2)) => num1.cmp(num2),
+            (num1, num2) => num1.to_rational().cmp(&num2.to_rational())
+        }
+    }
+    /// Raises the number to a whole power, without losing any precision
+    pub fn pow(&self, exp: usize) -> Number {
+        match *self {
+            Number::Decimal(ref num) => {
+                let (int, scale) = num.as_bigint_and_exponent();
+                Number::Decimal(BigDecimal::new(num::pow(int, exp), scale * exp as i64))
+            },
+            Number::Rational(ref num) => {
+                let numer = num::pow(num.numer().clone(), exp);
+                let denom = num::pow(num.denom().clone(), exp);
+                Number::Rational(BigRational::new_raw(numer, denom))
+            }
+        }
+    }
+    /// Returns the `n`th root of the positive number, if it can be written down exactly
+    pub fn exact_root(&self, n: usize) -> Option<Number> {
+        let num = self.to_rational();
+        // The root of a whole number above 1 is at least 2, so its power has at least `n` bits
+        if n == 0 || n > num.numer().bits().max(num.denom().bits()) {
+            return None;
+        }
+        let numer = math::iroot(num.numer(), n);
+        let denom = math::iroot(num.denom(), n);
+        if num::pow(numer.clone(), n) != *num.numer() || num::pow(denom.clone(), n) != *num.denom() {
+            return None;
+        }
+        let root = Number::Rational(BigRational::new(numer, denom));
+        match *self {
+            Number::Decimal(_) => root.to_exact_decimal().map(Number::Decimal),
+            Number::Rational(_) => Some(root)
+        }
+    }
+    /// Roughly how many digits it takes to store the number
+    pub fn digits(&self) -> f64 {
+        match *self {
+            Number::Decimal(ref num) => log10(&num.as_bigint_and_exponent().0),
+            Number::Rational(ref num) => log10(num.numer()) + log10(num.denom())
+        }
+    }
+    /// Roughly calculates log10 of the number, to tell how big it is
+    pub fn magnitude(&self) -> f64 {
+        match *self {
+            Number::Decimal(ref num) => {
+                let (int, scale) = num.as_bigint_and_exponent();
+                log10(&int) - scale as f64
+            },
+            Number::Rational(ref num) => log10(num.numer()) - log10(num.denom())
+        }
+    }
+    /// Roughly converts the number to an `f64`, which is infinite if it's too big to fit.
+    /// Unlike `to_f64`, this never fails.
+    pub fn approximate(&self) -> f64 {
+        let abs = 10f64.powf(self.magnitude());
+        if self.sign() == Sign::Minus { -abs } else { abs }
+    }
+    /// Divides the number by `other`. Decimals are rounded according to `precision`,
+    /// but if either side is a fraction the result is exact.
+    pub fn div(self, other: Number, precision: Precision) -> Number {
+        match (self, other) {
+            (Number::Decimal(num1), Number::Decimal(num2)) => Number::Decimal(div_decimal(&num1, &num2, precision)),
+            (num1, num2) => Number::Rational(num1.to_rational() / num2.to_rational())
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match *self {
+            Number::Decimal(ref num) => num.is_zero(),
+            Number::Rational(ref num) => num.is_zero()
+        }
+    }
+    pub fn is_whole(&self) -> bool {
+        match *self {
+            Number::Decimal(ref num) => num.with_scale(0) == *num,
+            Number::Rational(ref num) => num.is_integer()
+        }
+    }
+    pub fn sign(&self) -> Sign {
+        match *self {
+            Number::Decimal(ref num) => num.sign(),
+            Number::Rational(ref num) => num.numer().sign()
+        }
+    }
+    pub fn abs(&self) -> Self {
+        match *self {
+            Number::Decimal(ref num) => Number::Decimal(num.abs()),
+            Number::Rational(ref num) => Number::Rational(num.abs())
+        }
+    }
+}
+
+/// Roughly calculates log10 of a whole number, from its 53 highest bits
+pub fn log10(int: &BigInt) -> f64 {
+    let shift = int.bits().saturating_sub(53);
+    let top = (int.abs() >> shift).to_u64().unwrap() as f64;
+    top.log10() + shift as f64 * ::std::f64::consts::LOG10_2
+}
+/// Converts a decimal to a fraction without losing any precision
+pub fn decimal_to_rational(num: &BigDecimal) -> BigRational {
+    let (int, scale) = num.as_bigint_and_exponent();
+    if scale >= 0 {
+        BigRational::new(int, ten_to_the(scale as u64))
+    } else {
+        BigRational::from_integer(int * ten_to_the(-scale as u64))
+    }
+}
+/// Converts a fraction to a decimal, rounding fractions that don't terminate
+pub fn rational_to_decimal(num: &BigRational, precision: Precision) -> BigDecimal {
+    div_decimal(&BigDecimal::new(num.numer().clone(), 0), &BigDecimal::new(num.denom().clone(), 0), precision)
+}
+/// Divides `num1` by `num2`, rounding the result to `precision.scale` digits after the point.
+/// Results that fit in fewer digits are kept as short as possible.
+pub fn div_decimal(num1: &BigDecimal, num2: &BigDecimal, precision: Precision) -> BigDecimal {
+    let (mut int1, scale1) = num1.as_bigint_and_exponent();
+    let (mut int2, scale2) = num2.as_bigint_and_exponent();
+    if int2.is_negative() {
+        int1 = -int1;
+        int2 = -int2;
+    }
+
+    // num1 / num2 = int1 / int2 * 10^(scale2 - scale1), and we want it times 10^scale
+    let exp = scale2 - scale1 + precision.scale as i64;
+    if exp >= 0 {
+        int1 = int1 * ten_to_the(exp as u64);
+    } else {
+        int2 = int2 * ten_to_the(-exp as u64);
+    }
+
+    trim_zeros(BigDecimal::new(round_div(&int1, &int2, precision.rounding), precision.scale as i64))
+}
+/// Divides `num1` by the positive number `num2`, rounding the result to a whole number
+pub fn round_div(num1: &BigInt, num2: &BigInt, rounding: Rounding) -> BigInt {
+    let (quotient, remainder) = num1.div_rem(num2);
+    if remainder.is_zero() {
+        return quotient;
+    }
+
+    let away = if num1.is_negative() { -BigInt::one() } else { BigInt::one() };
+    let away_from_zero = match rounding {
+        Rounding::Truncate => false,
+        Rounding::Floor => num1.is_negative(),
+        Rounding::Ceiling => !num1.is_negative(),
+        Rounding::HalfUp | Rounding::HalfEven => {
+            use std::cmp::Ordering;
+            match (remainder.abs() * BigInt::from(2)).cmp(num2) {
+                Ordering::Less => false,
+                Ordering::Greater => true,
+                Ordering::Equal => rounding == Rounding::HalfUp || quotient.is_odd()
+            }
+        }
+    };
+
+    if away_from_zero {
+        quotient + away
+    } else {
+        quotient
+    }
+}
+/// Removes any trailing zeroes after the decimal point
+pub fn trim_zeros(num: BigDecimal) -> BigDecimal {
+    let (mut int, mut scale) = num.into_bigint_and_exponent();
+    let ten = BigInt::from(10);
+    while scale > 0 && !int.is_zero() {
+        let (quotient, remainder) = int.div_rem(&ten);
+        if !remainder.is_zero() {
+            break;
+        }
+        int = quotient;
+        scale -= 1;
+    }
+    if int.is_zero() {
+        scale = 0;
+    }
+    BigDecimal::new(int, scale)
+}
+
+impl From<BigDecimal> for Number {
+    fn from(num: BigDecimal) -> Self {
+        Number::Decimal(num)
+    }
+}
+impl From<BigRational> for Number {
+    fn from(num: BigRational) -> Self {
+        Number::Rational(num)
+    }
+}
+impl Neg for Number {
+    type Output = Number;
+
+    fn neg(self) -> Number {
+        match self {
+            Number::Decimal(num) => Number::Decimal(-num),
+            Number::Rational(num) => Number::Rational(-num)
+        }
+    }
+}
+impl ToPrimitive for Number {
+    fn to_i64(&self) -> Option<i64> {
+        match *self {
+            Number::Decimal(ref num) => num.to_i64(),
+            Number::Rational(ref num) => num.to_integer().to_i64()
+        }
+    }
+    fn to_u64(&self) -> Option<u64> {
+        match *self {
+            Number::Decimal(ref num) => num.to_u64(),
+            Number::Rational(ref num) => num.to_integer().to_u64()
+        }
+    }
+    fn to_f64(&self) -> Option<f64> {
+        match *self {
+            Number::Decimal(ref num) => num.to_f64(),
+            Number::Rational(ref num) => Some(num.numer().to_f64()? / num.denom().to_f64()?)
+        }
+    }
+}
+impl ToBigInt for Number {
+    fn to_bigint(&self) -> Option<BigInt> {
+        match *self {
+            Number::Decimal(ref num) => num.to_bigint(),
+            Number::Rational(ref num) => Some(num.to_integer())
+        }
+    }
+}
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Decimal(ref num) => write!(f, "{}", num),
+            Number::Rational(ref num) => write!(f, "{}", num)
+        }
+    }
+}
+
+macro_rules! impl_op {
+    ($trait:ident, $method:ident) => {
+        /// Decimals stay decimals, but if either side is a fraction the result is exact
+        impl $trait for Number {
+            type Output = Number;
+
+            fn $method(self, other: Number) -> Number {
+                match (self, other) {
+                    (Number::Decimal(num1), Number::Decimal(num2)) => Number::Decimal(num1.$method(num2)),
+                    (num1, num2) => Number::Rational(num1.to_rational().$method(num2.to_rational()))
+                }
+            }
+        }
+    }
+}
+impl_op!(Add, add);
+impl_op!(Sub, sub);
+impl_op!(Mul, mul);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(num: &str) -> Number {
+        Number::Decimal(num.parse().unwrap())
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let modes = [Rounding::Truncate, Rounding::HalfUp, Rounding::HalfEven, Rounding::Floor, Rounding::Ceiling];
+        // What each of the modes above rounds num1 / num2 to
+        let cases: [(i32, i32, [i32; 5]); 7] = [
+            (5, 2, [2, 3, 2, 2, 3]),
+            (-5, 2, [-2, -3, -2, -3, -2]),
+            (7, 2, [3, 4, 4, 3, 4]),
+            (7, 3, [2, 2, 2, 2, 3]),
+            (-7, 3, [-2, -2, -2, -3, -2]),
+            (8, 3, [2, 3, 3, 2, 3]),
+            (6, 3, [2, 2, 2, 2, 2])
+        ];
+        for &(num1, num2, ref expected) in cases.iter() {
+            for (&rounding, &expected) in modes.iter().zip(expected.iter()) {
+                let rounded = round_div(&BigInt::from(num1), &BigInt::from(num2), rounding);
+                assert_eq!(rounded, BigInt::from(expected), "{} / {} with {:?}", num1, num2, rounding);
+            }
+        }
+    }
+
+    #[test]
+    fn widths() {
+        let u8_width = Width { bits: 8, signed: false, strict: false };
+        let i8_width = Width { bits: 8, signed: true, strict: false };
+        // A number, and what it wraps around to as a u8 and as an i8
+        let cases = [
+            (255, 255, -1), (256, 0, 0), (300, 44, 44), (-1, 255, -1),
+            (127, 127, 127), (128, 128, -128), (-129, 127, 127)
+        ];
+        for &(num, unsigned, signed) in cases.iter() {
+            assert_eq!(u8_width.wrap(&BigInt::from(num)), BigInt::from(unsigned), "{} as u8", num);
+            assert_eq!(i8_width.wrap(&BigInt::from(num)), BigInt::from(signed), "{} as i8", num);
+        }
+        assert_eq!(i8_width.to_unsigned(&BigInt::from(-1)), BigInt::from(255));
+
+        // Fitting cuts off the fraction first, and only a strict width refuses what doesn't fit
+        assert_eq!(u8_width.fit(&decimal("257.9"), Mode::Decimal).unwrap(), decimal("1"));
+        assert_eq!(i8_width.fit(&decimal("-2.5"), Mode::Decimal).unwrap(), decimal("-2"));
+        let strict = Width { strict: true, ..u8_width };
+        assert_eq!(strict.fit(&decimal("255"), Mode::Decimal).unwrap(), decimal("255"));
+        assert!(strict.fit(&decimal("256"), Mode::Decimal).is_err());
+        assert!(strict.fit(&decimal("-1"), Mode::Decimal).is_err());
+    }
+
+    #[test]
+    fn exact_roots() {
+        assert_eq!(decimal("27").exact_root(3), Some(decimal("3")));
+        assert_eq!(decimal("0.001").exact_root(3), Some(decimal("0.1")));
+        assert_eq!(decimal("2").exact_root(2), None);
+        let fraction = Number::Rational(BigRational::new(BigInt::from(4), BigInt::from(9)));
+        assert_eq!(fraction.exact_root(2), Some(Number::Rational(BigRational::new(BigInt::from(2), BigInt::from(3)))));
+    }
+}
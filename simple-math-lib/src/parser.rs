@@ -5,11 +5,16 @@ use std::{fmt, mem};
 /// A token
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Token {
+    Assign,
     BlockName(String),
     Num(BigDecimal),
     ParenClose,
     ParenOpen,
+    Semicolon,
     Separator,
+    Then,
+    Else,
+    Global,
     VarAssign(String),
     VarGet(String),
 
@@ -18,9 +23,17 @@ pub enum Token {
     BitshiftLeft,
     BitshiftRight,
     Div,
+    Equal,
     Factorial,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    LogicalAnd,
+    LogicalOr,
     Mul,
     Not,
+    NotEqual,
     Or,
     Pow,
     Rem,
@@ -31,11 +44,16 @@ pub enum Token {
 impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
+            Token::Assign => write!(f, "Function definition (=)"),
             Token::BlockName(ref name) => write!(f, "\"{}\"", name),
             Token::Num(ref num) => write!(f, "Number {}", num),
             Token::ParenClose => write!(f, ")"),
             Token::ParenOpen => write!(f, "("),
+            Token::Semicolon => write!(f, ";"),
             Token::Separator => write!(f, ","),
+            Token::Then => write!(f, "Conditional (?)"),
+            Token::Else => write!(f, "Conditional else (:)"),
+            Token::Global => write!(f, "Keyword global"),
             Token::VarAssign(ref name) => write!(f, "Variable assignment \"{}\"", name),
             Token::VarGet(ref name) => write!(f, "Variable \"{}\"", name),
 
@@ -44,9 +62,17 @@ impl fmt::Display for Token {
             Token::BitshiftLeft => write!(f, "Bitshift left (<<)"),
             Token::BitshiftRight => write!(f, "Bitshift right (>>)"),
             Token::Div => write!(f, "Division symbol (/)"),
+            Token::Equal => write!(f, "Equal to (==)"),
             Token::Factorial => write!(f, "Factorial (!)"),
+            Token::Greater => write!(f, "Greater than (>)"),
+            Token::GreaterEqual => write!(f, "Greater than or equal to (>=)"),
+            Token::Less => write!(f, "Less than (<)"),
+            Token::LessEqual => write!(f, "Less than or equal to (<=)"),
+            Token::LogicalAnd => write!(f, "Logical AND (&&)"),
+            Token::LogicalOr => write!(f, "Logical OR (||)"),
             Token::Mul => write!(f, "Times (*)"),
             Token::Not => write!(f, "Bitwise NOT (~)"),
+            Token::NotEqual => write!(f, "Not equal to (!=)"),
             Token::Or => write!(f, "Bitwise OR (|)"),
             Token::Pow => write!(f, "Exponential (**)"),
             Token::Rem => write!(f, "Remainder (%)"),
@@ -56,19 +82,42 @@ impl fmt::Display for Token {
     }
 }
 
+/// A range of characters in the input, from `start` up to but not including `end`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+    /// Returns the smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start, other.end)
+    }
+}
+
 /// An error when parsing
 #[derive(Debug, Fail)]
 pub enum ParseError {
     #[fail(display = "Character '{}' neither a number nor a valid letter \
-                      in a function or variable name.", _0)]
-    DisallowedChar(char),
+                      in a function or variable name.", _1)]
+    DisallowedChar(Span, char),
     #[fail(display = "You may only use whole numbers in this context")]
-    DisallowedDecimal,
-    #[fail(display = "\"{}\" is not a valid variable name.", _0)]
-    DisallowedVariable(String),
-    #[fail(display = "Character '{0}' isn't followed by another '{0}'.\n\
-                      Looks like a failed attempt to bitshift.", _0)]
-    UnclosedBitShift(char)
+    DisallowedDecimal(Span),
+    #[fail(display = "\"{}\" is not a valid variable name.", _1)]
+    DisallowedVariable(Span, String)
+}
+impl ParseError {
+    /// Returns the part of the input this error is about
+    pub fn span(&self) -> Span {
+        match *self {
+            ParseError::DisallowedChar(span, _) |
+            ParseError::DisallowedDecimal(span) |
+            ParseError::DisallowedVariable(span, _) => span
+        }
+    }
 }
 impl Into<CalcError> for ParseError {
     fn into(self) -> CalcError {
@@ -76,30 +125,34 @@ impl Into<CalcError> for ParseError {
     }
 }
 
-/// "Parse" the string into a list of tokens.
+/// "Parse" the string into a list of tokens, each with the span it came from.
 /// This is technically actually a tokenizer...
-pub fn parse(input: &str) -> Result<Vec<Token>, ParseError> {
+pub fn parse(input: &str) -> Result<Vec<(Span, Token)>, ParseError> {
     let mut output = Vec::new();
     let mut buffer = String::new();
+    let mut buffer_span = Span::default();
 
     macro_rules! prepare_var {
         () => {
-            if let Some(&Token::Num(_)) = output.last() {
-                output.push(Token::Mul);
+            if let Some(&(_, Token::Num(_))) = output.last() {
+                output.push((Span::new(buffer_span.start, buffer_span.start), Token::Mul));
             }
         }
     }
     macro_rules! flush {
         () => {
-            if !buffer.is_empty() {
+            if buffer == "global" {
+                buffer.clear();
+                output.push((buffer_span, Token::Global));
+            } else if !buffer.is_empty() {
                 let buffer = mem::replace(&mut buffer, String::new());
                 match parse_num(&buffer) {
                     Ok(num) => {
-                        output.push(Token::Num(num));
+                        output.push((buffer_span, Token::Num(num)));
                     },
                     Err(_) => {
                         prepare_var!();
-                        output.push(Token::VarGet(buffer));
+                        output.push((buffer_span, Token::VarGet(buffer)));
                     }
                 }
             }
@@ -108,68 +161,107 @@ pub fn parse(input: &str) -> Result<Vec<Token>, ParseError> {
 
     let mut chars = input.chars().enumerate().peekable();
     while let Some((i, c)) = chars.next() {
+        let mut end = i + 1;
+        // Picks a token depending on which character comes next, consuming it if it matches
+        macro_rules! followed_by {
+            ($otherwise:expr, $($next:expr => $token:expr),+) => {
+                match chars.peek() {
+                    $(Some(&(_, next)) if next == $next => {
+                        chars.next();
+                        end += 1;
+                        $token
+                    },)+
+                    _ => $otherwise
+                }
+            }
+        }
         let token = match c {
-            ' ' => continue,
+            ' ' => {
+                // Spaces don't end names, except for keywords
+                if buffer == "global" {
+                    flush!();
+                }
+                continue
+            },
             ',' => Some(Token::Separator),
+            ';' => Some(Token::Semicolon),
+            '?' => Some(Token::Then),
+            ':' => Some(Token::Else),
             ')' => Some(Token::ParenClose),
             '+' => Some(Token::Add),
             '-' => Some(Token::Sub),
             '*' => if let Some(&(_, '*')) = chars.peek() {
                     chars.next();
+                    end += 1;
                     Some(Token::Pow)
                 } else {
                     Some(Token::Mul)
                 },
             '/' => Some(Token::Div),
             '%' => Some(Token::Rem),
-            '&' => Some(Token::And),
-            '|' => Some(Token::Or),
+            '&' => Some(followed_by!(Token::And, '&' => Token::LogicalAnd)),
+            '|' => Some(followed_by!(Token::Or, '|' => Token::LogicalOr)),
             '^' => Some(Token::Xor),
-            '<' => {
-                if chars.next() != Some((i+1, '<')) {
-                    return Err(ParseError::UnclosedBitShift('<'));
-                }
-                Some(Token::BitshiftLeft)
-            },
-            '>' => {
-                if chars.next() != Some((i+1, '>')) {
-                    return Err(ParseError::UnclosedBitShift('>'));
-                }
-                Some(Token::BitshiftRight)
-            },
+            '<' => Some(followed_by!(Token::Less, '<' => Token::BitshiftLeft, '=' => Token::LessEqual)),
+            '>' => Some(followed_by!(Token::Greater, '>' => Token::BitshiftRight, '=' => Token::GreaterEqual)),
             '~' => Some(Token::Not),
-            '!' => Some(Token::Factorial),
+            '!' => Some(followed_by!(Token::Factorial, '=' => Token::NotEqual)),
+            '=' => followed_by!(None, '=' => Some(Token::Equal)),
             _   => None
         };
 
         if let Some(token) = token {
             flush!();
-            output.push(token);
+            output.push((Span::new(i, end), token));
         } else if c == '(' {
+            if buffer == "global" {
+                flush!();
+            }
             if !buffer.is_empty() {
                 match parse_num(&buffer) {
                     Ok(num) => {
-                        output.push(Token::Num(num));
-                        output.push(Token::Mul);
+                        output.push((buffer_span, Token::Num(num)));
+                        output.push((Span::new(i, i), Token::Mul));
                     },
                     Err(_) => {
-                        output.push(Token::BlockName(buffer));
+                        output.push((buffer_span, Token::BlockName(buffer)));
                     }
                 };
                 buffer = String::new();
             }
-            output.push(Token::ParenOpen);
+            output.push((Span::new(i, end), Token::ParenOpen));
         } else if c == '=' {
             let buffer = mem::replace(&mut buffer, String::new());
-            if buffer.is_empty() || is_num(&buffer) || buffer.starts_with('$') || buffer.starts_with('0') {
-                return Err(ParseError::DisallowedVariable(buffer));
+            if buffer.is_empty() {
+                match output.last() {
+                    Some(&(_, Token::ParenClose)) => {
+                        // Defining a function with a parameter list, like f(x, y) = ...
+                        output.push((Span::new(i, end), Token::Assign));
+                        continue;
+                    },
+                    Some(&(span, Token::Global)) => {
+                        return Err(ParseError::DisallowedVariable(span.to(Span::new(i, end)), "global".to_string()));
+                    },
+                    _ => ()
+                }
             }
-            output.push(Token::VarAssign(buffer));
+            let span = if buffer.is_empty() {
+                Span::new(i, end)
+            } else {
+                buffer_span.to(Span::new(i, end))
+            };
+            if buffer.is_empty() || is_num(&buffer) || buffer.starts_with('$') || buffer.starts_with('0') || buffer == "global" {
+                return Err(ParseError::DisallowedVariable(span, buffer));
+            }
+            output.push((span, Token::VarAssign(buffer)));
         } else {
             let code = c as u32;
             let was_num = is_num(&buffer);
             let old_len = buffer.len();
 
+            if buffer.is_empty() {
+                buffer_span.start = i;
+            }
             buffer.push(c);
             let num = is_num(&buffer);
             if num ||
@@ -182,13 +274,15 @@ pub fn parse(input: &str) -> Result<Vec<Token>, ParseError> {
                     buffer.drain(old_len..);
                     flush!();
                     buffer.push(c);
+                    buffer_span.start = i;
                 }
+                buffer_span.end = end;
             } else {
                 if c == '.' {
-                    return Err(ParseError::DisallowedDecimal);
+                    return Err(ParseError::DisallowedDecimal(Span::new(i, end)));
                 }
                 buffer.drain(old_len..);
-                return Err(ParseError::DisallowedChar(c));
+                return Err(ParseError::DisallowedChar(Span::new(i, end), c));
             }
         }
     }
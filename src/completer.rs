@@ -0,0 +1,70 @@
+use simple_math_lib::calculator::{self, Context};
+use simple_math_lib::math;
+use COMMANDS;
+
+/// Completes the names of variables, functions, constants and REPL commands.
+/// The names are a snapshot of the context, so a new completer is made for each line.
+/// The highlighter also asks it which names are defined.
+pub struct NameCompleter {
+    variables: Vec<String>,
+    functions: Vec<String>
+}
+impl NameCompleter {
+    pub fn new(context: &Context) -> Self {
+        let mut variables: Vec<String> = context.variables.keys().cloned()
+            .chain(math::CONSTANTS.iter().map(|name| name.to_string()))
+            .collect();
+        let mut functions: Vec<String> = context.functions.keys().cloned()
+            .chain(calculator::BUILTINS.iter().map(|name| name.to_string()))
+            .collect();
+        variables.sort();
+        variables.dedup();
+        functions.sort();
+        functions.dedup();
+
+        NameCompleter {
+            variables,
+            functions
+        }
+    }
+
+    /// Returns true if `name` is a variable or a constant
+    pub fn is_variable(&self, name: &str) -> bool {
+        self.variables.binary_search_by(|variable| variable.as_str().cmp(name)).is_ok()
+    }
+    /// Returns true if `name` is a function or a builtin
+    pub fn is_function(&self, name: &str) -> bool {
+        self.functions.binary_search_by(|function| function.as_str().cmp(name)).is_ok()
+    }
+
+    /// Returns where the word being completed starts, and what it could be completed to
+    pub fn complete(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+
+        if before.starts_with(':') && !before.contains(' ') {
+            let candidates = COMMANDS.iter()
+                .map(|&(usage, _)| format!(":{}", usage.split(' ').next().unwrap()))
+                .filter(|command| command.starts_with(before))
+                .collect();
+            return (0, candidates);
+        }
+
+        let start = before.char_indices()
+            .rev()
+            .take_while(|&(_, c)| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+            .last()
+            .map_or(pos, |(i, _)| i);
+        let word = &before[start..];
+        if word.is_empty() {
+            return (start, Vec::new());
+        }
+
+        let variables = self.variables.iter()
+            .filter(|name| name.starts_with(word))
+            .cloned();
+        let functions = self.functions.iter()
+            .filter(|name| name.starts_with(word))
+            .map(|name| format!("{}(", name));
+        (start, variables.chain(functions).collect())
+    }
+}
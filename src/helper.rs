@@ -0,0 +1,254 @@
+use completer::NameCompleter;
+use rustyline::{self, Helper};
+use rustyline::completion::Completer;
+use rustyline::highlight::{CmdKind, Highlighter};
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use simple_math_lib::ast;
+use simple_math_lib::calculator::{Context, Function, Limits, Outcome};
+use simple_math_lib::number::Number;
+use simple_math_lib::parser::{self, Span, Token};
+use std::borrow::Cow::{self, Borrowed, Owned};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+use {apply_settings, run_statements, try_format};
+
+const NUMBER: &str = "\x1b[36m";
+const OPERATOR: &str = "\x1b[33m";
+const NAME: &str = "\x1b[32m";
+const KEYWORD: &str = "\x1b[35m";
+const UNKNOWN: &str = "\x1b[31m";
+const HINT: &str = "\x1b[90m";
+const RESET: &str = "\x1b[0m";
+
+/// How much work the live preview may do on every key press, so typing stays responsive
+const PREVIEW_LIMITS: Limits = Limits {
+    max_digits: 1000,
+    max_approx_digits: 1000,
+    max_steps: 10_000
+};
+/// How long a key press waits for the live preview, before leaving it out
+const PREVIEW_TIME: Duration = Duration::from_millis(50);
+/// The stack the preview thread gets, as much as the main thread usually has.
+/// The depth limits of the parser and calculator count on it.
+const PREVIEW_STACK: usize = 8 * 1024 * 1024;
+/// The most characters of a result the live preview shows
+const PREVIEW_LEN: usize = 40;
+
+/// Helps rustyline edit a line: completes names, highlights the input and previews its result.
+/// Like the completer it works on a snapshot of the context, so a new helper is made for each line.
+pub struct ReplHelper {
+    names: NameCompleter,
+    /// Lines for the preview thread to calculate
+    lines: Sender<String>,
+    /// The lines the preview thread calculated, each with its preview
+    previews: Receiver<(String, Option<String>)>,
+    /// How many lines were sent that haven't come back yet
+    pending: Cell<usize>
+}
+impl ReplHelper {
+    pub fn new(context: &Context) -> Self {
+        let (lines, line_receiver) = mpsc::channel::<String>();
+        let (preview_sender, previews) = mpsc::channel();
+        let mut variables = context.variables.clone();
+        let mut functions = context.functions.clone();
+
+        // Some lines take a while to calculate even within the limits, so that happens on its own thread
+        // that typing never waits long for. It stops once the helper is dropped with the line.
+        let spawned = thread::Builder::new().stack_size(PREVIEW_STACK).spawn(move || {
+            let side_effects = functions.values().any(|function| function.body.has_side_effects());
+            while let Ok(mut line) = line_receiver.recv() {
+                // Lines typed over in the meantime don't need a preview anymore
+                while let Ok(newer) = line_receiver.try_recv() {
+                    preview_sender.send((line, None)).ok();
+                    line = newer;
+                }
+                let preview = preview(&mut variables, &mut functions, side_effects, &line);
+                if preview_sender.send((line, preview)).is_err() {
+                    break;
+                }
+            }
+        });
+        if let Err(err) = spawned {
+            eprintln!("Warning: Could not start the live preview: {}", err);
+        }
+
+        ReplHelper {
+            names: NameCompleter::new(context),
+            lines,
+            previews,
+            pending: Cell::new(0)
+        }
+    }
+}
+
+/// Calculates `line` without assigning or defining anything, returning its result if it has one.
+/// `side_effects` tells if calling any of the functions can change the variables or functions.
+fn preview(
+    variables: &mut HashMap<String, Number>,
+    functions: &mut HashMap<String, Function>,
+    side_effects: bool,
+    line: &str
+) -> Option<String> {
+    let tokens = parser::parse(line).ok()?;
+    let expr = ast::parse(tokens.into_iter()).ok()?;
+
+    // Plain assignments go to a scope of their own, but anything else that can change
+    // the variables or functions needs to work on a copy of them
+    let (mut variables_copy, mut functions_copy);
+    let (variables, functions) = if side_effects || expr.has_side_effects() {
+        variables_copy = variables.clone();
+        functions_copy = functions.clone();
+        (&mut variables_copy, &mut functions_copy)
+    } else {
+        (variables, functions)
+    };
+    let mut context = Context::new(variables, functions);
+    context.scope = Some(HashMap::new());
+    let settings = |context: &mut Context| {
+        apply_settings(context, false);
+        context.limits = Limits {
+            max_digits: context.limits.max_digits.min(PREVIEW_LIMITS.max_digits),
+            max_approx_digits: context.limits.max_approx_digits.min(PREVIEW_LIMITS.max_approx_digits),
+            max_steps: context.limits.max_steps.min(PREVIEW_LIMITS.max_steps)
+        };
+    };
+
+    match run_statements(&mut context, &expr, settings) {
+        Ok(Outcome::Value(result)) => try_format(&context, &result),
+        _ => None
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _: &rustyline::Context) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.names.complete(line, pos))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+
+    fn hint(&self, line: &str, pos: usize, _: &rustyline::Context) -> Option<String> {
+        // Only preview while typing at the end of an expression
+        if pos < line.len() || line.trim().is_empty() || line.starts_with(':') {
+            return None;
+        }
+        // Previews of lines typed over since don't matter anymore
+        while self.previews.try_recv().is_ok() {
+            self.pending.set(self.pending.get() - 1);
+        }
+        let busy = self.pending.get() > 0;
+        self.lines.send(line.to_string()).ok()?;
+        self.pending.set(self.pending.get() + 1);
+        // Don't hold up typing behind an older line that's still being calculated
+        if busy {
+            return None;
+        }
+
+        let deadline = Instant::now() + PREVIEW_TIME;
+        let mut result = loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (previewed, preview) = self.previews.recv_timeout(timeout).ok()?;
+            self.pending.set(self.pending.get() - 1);
+            if previewed == line {
+                break preview?;
+            }
+        };
+        if result == line.trim() {
+            return None;
+        }
+        if result.chars().count() > PREVIEW_LEN {
+            result = result.chars().take(PREVIEW_LEN).collect();
+            result.push_str("...");
+        }
+        Some(format!("  = {}", result))
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, _: usize) -> Cow<'l, str> {
+        if line.starts_with(':') {
+            return Borrowed(line);
+        }
+        let mut colors: Vec<Option<&str>> = vec![None; line.chars().count()];
+        let mut paint = |span: Span, color: &'static str| {
+            for slot in &mut colors[span.start..span.end] {
+                *slot = Some(color);
+            }
+        };
+
+        // Color what could be parsed, and mark where parsing stopped
+        let tokens = match parser::parse(line) {
+            Ok(tokens) => tokens,
+            Err(err) => {
+                let span = err.span();
+                paint(span, UNKNOWN);
+                let before: String = line.chars().take(span.start).collect();
+                parser::parse(&before).unwrap_or_default()
+            }
+        };
+
+        // Parameters aren't defined yet while defining a function, so don't mark any name there
+        let defining = tokens.windows(2).any(|pair| matches!(
+            (&pair[0].1, &pair[1].1),
+            (&Token::VarAssign(_), &Token::ParenOpen) | (_, &Token::Assign)
+        ));
+        let mut assigned = HashSet::new();
+
+        for (span, token) in tokens {
+            match token {
+                Token::Num(_) => paint(span, NUMBER),
+                Token::Global => paint(span, KEYWORD),
+                Token::VarAssign(name) => {
+                    paint(Span::new(span.start, span.end - 1), NAME);
+                    paint(Span::new(span.end - 1, span.end), OPERATOR);
+                    assigned.insert(name);
+                },
+                Token::VarGet(name) => if defining || assigned.contains(&name) || self.names.is_variable(&name) {
+                    paint(span, NAME);
+                } else {
+                    paint(span, UNKNOWN);
+                },
+                Token::BlockName(name) => if defining || self.names.is_function(&name) {
+                    paint(span, NAME);
+                } else {
+                    paint(span, UNKNOWN);
+                },
+                Token::ParenOpen | Token::ParenClose | Token::Separator | Token::Semicolon => (),
+                _ => paint(span, OPERATOR)
+            }
+        }
+
+        let mut output = String::with_capacity(line.len());
+        let mut current = None;
+        for (c, color) in line.chars().zip(colors) {
+            if color != current {
+                output.push_str(color.unwrap_or(RESET));
+                current = color;
+            }
+            output.push(c);
+        }
+        if current.is_some() {
+            output.push_str(RESET);
+        }
+        Owned(output)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Owned(format!("{}{}{}", HINT, hint, RESET))
+    }
+
+    fn highlight_char(&self, _: &str, _: usize, kind: CmdKind) -> bool {
+        kind != CmdKind::MoveCursor
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
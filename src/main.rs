@@ -3,32 +3,151 @@ extern crate num;
 extern crate rustyline;
 extern crate simple_math_lib;
 
+mod completer;
+mod helper;
+mod session;
+
 use bigdecimal::BigDecimal;
+use helper::ReplHelper;
 use rustyline::Editor;
+use rustyline::history::{DefaultHistory, History};
 use rustyline::error::ReadlineError;
-use simple_math_lib::*;
+use simple_math_lib::{ast, integer, parser};
+use simple_math_lib::ast::{Expr, ExprKind};
+use simple_math_lib::calculator::{self, CalcError, Context, Limits, Outcome};
+use simple_math_lib::number::{Mode, Number, Precision, Rounding, Width};
 use std::collections::HashMap;
-use std::env;
+use std::io::{self, IsTerminal, Read};
+use std::path::Path;
+use std::{env, fs, process, slice};
+
+/// The REPL commands, with their arguments, and what they do
+const COMMANDS: [(&str, &str); 12] = [
+    ("vars", "List all variables"),
+    ("funcs", "List all functions"),
+    ("show <name>", "Show a variable or function"),
+    ("del <name>", "Delete a variable or function"),
+    ("reset", "Delete everything, and set the special variables back to their defaults"),
+    ("save <file>", "Save all variables and functions to a file"),
+    ("load <file>", "Load variables and functions from a file"),
+    ("factor <expr>", "Split the result of an expression into its prime factors"),
+    ("results", "List the earlier results, which are kept in _1, _2, ... and the latest in ans"),
+    ("history [text]", "List the lines entered before, or only those containing some text"),
+    ("help", "Show this list"),
+    ("quit", "Quit")
+];
+/// The special variables that change how lines are calculated and shown, and what they do
+const SETTINGS: [(&str, &str); 14] = [
+    ("out", "The base results are written in: 2, 8, 10 or 16"),
+    ("exact", "Whether to calculate with exact fractions (1) instead of decimals (0)"),
+    ("digits", "How many decimal places results are written with, or 0 for as many as they have"),
+    ("scale", "How many digits after the point results that can't be exact keep"),
+    ("rounding", "How those are rounded: 0 truncate, 1 half up, 2 half even, 3 floor, 4 ceiling"),
+    ("bits", "The integer size results wrap around to: 8, 16, 32, 64, 128, 256, or 0 for none"),
+    ("signed", "Whether that integer size is signed (1) or unsigned (0)"),
+    ("overflow", "Whether results that don't fit that size are an error (1) instead of wrapping (0)"),
+    ("maxdigits", "The most digits a result may have"),
+    ("maxapprox", "The most digits an approximated result, like from exp or sin, may have"),
+    ("maxsteps", "The most steps a loop may take, or functions a statement may call"),
+    ("histsize", "How many lines the history keeps"),
+    ("histdups", "Whether to keep repeated lines in the history (1) instead of moving them to the end (0)"),
+    ("histerrors", "Whether to keep lines that failed in the history")
+];
 
 fn main() {
     let mut terminate = false;
     let mut variables = HashMap::new();
-    variables.insert("out".to_string(), BigDecimal::from(10));
+    set_defaults(&mut variables);
     let mut functions = HashMap::new();
+    let mut context = Context::new(&mut variables, &mut functions);
 
-    for arg in env::args().skip(1) {
-        if let Some(output) = calculate(&arg, &mut variables, &mut functions) {
-            println!("{}", output);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let keep_going = args.iter().any(|arg| arg == "--keep-going");
+    let mut failed = false;
+    let mut args = args.into_iter().filter(|arg| arg != "--keep-going");
+    while let Some(arg) = args.next() {
+        terminate = true;
+        if arg == "-f" {
+            let file = match args.next() {
+                Some(file) => file,
+                None => {
+                    eprintln!("Error: -f needs a script to run");
+                    process::exit(1);
+                }
+            };
+            let script = match fs::read_to_string(&file) {
+                Ok(script) => script,
+                Err(err) => {
+                    eprintln!("Error: Could not read {}: {}", file, err);
+                    process::exit(1);
+                }
+            };
+            if !run_script(&file, &script, &mut context, keep_going) {
+                failed = true;
+                if !keep_going {
+                    break;
+                }
+            }
+            continue;
+        }
+        match calculate(&arg, &mut context) {
+            Ok(Outcome::Value(result)) => println!("{}", format(&context, &result)),
+            Ok(Outcome::Assigned(name, value)) => println!("{} = {}", name, format(&context, &value)),
+            Ok(Outcome::Defined(_)) => (),
+            Err(err) => {
+                eprintln!("{}", arg);
+                print_error(0, &err);
+            }
         }
+    }
+
+    if !terminate && !io::stdin().is_terminal() {
         terminate = true;
+        let mut script = String::new();
+        if let Err(err) = io::stdin().read_to_string(&mut script) {
+            eprintln!("Read from STDIN failed.");
+            eprintln!("Details: {}", err);
+            process::exit(1);
+        }
+        failed = !run_script("<stdin>", &script, &mut context, keep_going);
     }
 
+    if failed {
+        process::exit(1);
+    }
     if terminate {
         return;
     }
 
-    let mut rl = Editor::<()>::new();
+    let session = session::default_path();
+    if let Some(ref path) = session {
+        if path.exists() {
+            if let Err(err) = session::load(path, &mut context) {
+                eprintln!("Warning: Could not restore the last session: {}", err);
+            }
+        }
+    }
+
+    let history = session::history_path();
+    let mut rl = match Editor::<ReplHelper, DefaultHistory>::new() {
+        Ok(rl) => rl,
+        Err(err) => {
+            eprintln!("Could not start the line editor.");
+            eprintln!("Details: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(ref path) = history {
+        if path.exists() {
+            let loaded = rl.history_mut().set_max_len(setting(&context, "histsize").unwrap_or(1000) as usize)
+                .and_then(|()| rl.load_history(path));
+            if let Err(err) = loaded {
+                eprintln!("Warning: Could not load the history from {}: {}", path.display(), err);
+            }
+        }
+    }
     loop {
+        rl.set_helper(Some(ReplHelper::new(&context)));
         let input = match rl.readline("> ") {
             Ok(input) => input,
             Err(ReadlineError::Interrupted) |
@@ -42,38 +161,402 @@ fn main() {
         if input.is_empty() {
             continue;
         }
-        rl.add_history_entry(&input);
-        if let Some(output) = calculate(&input, &mut variables, &mut functions) {
-            println!("= {}", output);
+        if let Some(command) = input.strip_prefix(':') {
+            add_history(rl.history_mut(), &context, &input);
+            if !run_command(command, &mut context, rl.history()) {
+                break;
+            }
+            continue;
+        }
+        let success = match calculate(&input, &mut context) {
+            Ok(Outcome::Value(result)) => {
+                println!("= {}", format(&context, &result));
+                record_result(&mut context, result);
+                true
+            },
+            Ok(Outcome::Assigned(name, value)) => {
+                println!("{} = {}", name, format(&context, &value));
+                true
+            },
+            Ok(Outcome::Defined(_)) => true,
+            Err(err) => { print_error(2, &err); false }
+        };
+        if success || setting(&context, "histerrors") != Some(0) {
+            add_history(rl.history_mut(), &context, &input);
+        }
+    }
+
+    if let Some(ref path) = history {
+        let saved = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).map_err(|err| err.to_string()),
+            None => Ok(())
+        }.and_then(|()| rl.save_history(path).map_err(|err| err.to_string()));
+        if let Err(err) = saved {
+            eprintln!("Warning: Could not save the history to {}: {}", path.display(), err);
+        }
+    }
+
+    if let Some(ref path) = session {
+        if let Err(err) = session::save(path, &context) {
+            eprintln!("Warning: Could not save the session to {}: {}", path.display(), err);
         }
     }
 }
 
-pub fn calculate(
-        input: &str,
-        variables: &mut HashMap<String, BigDecimal>,
-        functions: &mut HashMap<String, Vec<parser::Token>>
-    ) -> Option<String> {
-    use num::ToPrimitive;
-    match parse_and_calc(input, variables, functions) {
-        Ok(result) => {
-            use num::Zero;
-            use num::bigint::ToBigInt;
-            if result.is_zero() {
-                return None;
-            }
-            match variables.get("out").unwrap().to_u8() {
-                Some(2)  => return Some(format!("{:b}", result.to_bigint().unwrap())),
-                Some(8)  => return Some(format!("{:o}", result.to_bigint().unwrap())),
-                Some(10) => return Some(result.to_string()),
-                Some(16) => return Some(format!("{:X}", result.to_bigint().unwrap())),
-                _  => {
-                    eprintln!("Warning: Unsupported \"out\" variable value");
-                    return Some(result.to_string())
-                },
+/// Runs a REPL command, which is a line starting with `:`.
+/// Returns false if the REPL should quit.
+fn run_command(input: &str, context: &mut Context, history: &DefaultHistory) -> bool {
+    let mut parts = input.trim().splitn(2, ' ');
+    let command = parts.next().unwrap();
+    let arg = parts.next().map(str::trim).unwrap_or("");
+
+    match command {
+        "save" | "load" if arg.is_empty() => eprintln!("Error: :{} needs a file", command),
+        "show" | "del" if arg.is_empty() => eprintln!("Error: :{} needs a name", command),
+        "factor" if arg.is_empty() => eprintln!("Error: :factor needs an expression"),
+        "save" => if let Err(err) = session::save(Path::new(arg), context) {
+            eprintln!("Error: Could not save {}: {}", arg, err);
+        },
+        "load" => if let Err(err) = session::load(Path::new(arg), context) {
+            eprintln!("Error: {}", err);
+        },
+        "vars" => {
+            let mut variables: Vec<_> = context.variables.iter().collect();
+            variables.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, value) in variables {
+                println!("{} = {}", name, format(context, value));
+            }
+        },
+        "funcs" => {
+            let mut functions: Vec<_> = context.functions.iter().collect();
+            functions.sort_by(|a, b| a.0.cmp(b.0));
+            for (name, function) in functions {
+                println!("{}", session::definition(name, function));
+            }
+        },
+        "show" => {
+            let function = context.functions.get(arg);
+            let variable = context.variables.get(arg);
+            if let Some(function) = function {
+                println!("{}", session::definition(arg, function));
+            }
+            if let Some(value) = variable {
+                println!("{} = {}", arg, format(context, value));
+            }
+            if function.is_none() && variable.is_none() {
+                eprintln!("Error: Nothing named \"{}\" is defined", arg);
+            }
+        },
+        "del" => {
+            let function = context.functions.remove(arg);
+            let variable = context.variables.remove(arg);
+            if function.is_none() && variable.is_none() {
+                eprintln!("Error: Nothing named \"{}\" is defined", arg);
+            }
+        },
+        "reset" => {
+            context.variables.clear();
+            context.functions.clear();
+            set_defaults(context.variables);
+        },
+        "help" => {
+            let width = COMMANDS.iter().map(|&(usage, _)| usage.len()).max().unwrap();
+            for &(usage, description) in COMMANDS.iter() {
+                println!(":{:<width$}  {}", usage, description, width = width);
+            }
+            println!();
+            println!("Special variables, which apply from the statement after they're set:");
+            let width = SETTINGS.iter().map(|&(name, _)| name.len()).max().unwrap();
+            for &(name, description) in SETTINGS.iter() {
+                println!("  {:<width$}  {}", name, description, width = width);
+            }
+        },
+        "factor" => match factor(arg, context) {
+            Ok(product) => println!("{}", product),
+            Err(err) => {
+                eprintln!("{}", arg);
+                print_error(0, &err);
+            }
+        },
+        "results" => {
+            for (i, result) in results(context) {
+                println!("_{} = {}", i, format(context, result));
             }
         },
-        Err(err) => eprintln!("Error: {}", err)
+        "history" => {
+            for (i, line) in history.iter().enumerate() {
+                if line.contains(arg) {
+                    println!("{:>5}  {}", i + 1, line);
+                }
+            }
+        },
+        "quit" => return false,
+        _ => eprintln!("Error: Unknown command :{}, see :help", command)
+    }
+    true
+}
+
+/// Calculates `input` and writes the result as a product of its prime factors
+fn factor(input: &str, context: &mut Context) -> Result<String, CalcError> {
+    use num::bigint::ToBigInt;
+    use num::{Signed, Zero};
+    let num = match calculate(input, context)? {
+        Outcome::Value(num) | Outcome::Assigned(_, num) => num,
+        Outcome::Defined(_) => return Err(CalcError::InvalidSyntax)
+    };
+    if !num.is_whole() {
+        return Err(CalcError::NotAWhole);
+    }
+    let int = num.to_bigint().unwrap();
+    if int.is_zero() {
+        return Err(CalcError::OutOfDomain("factor"));
+    }
+
+    let factors = integer::factorize(&int, context.limits.max_steps)?;
+    let product = match (factors.is_empty(), int.is_negative()) {
+        (true, false) => "1".to_string(),
+        (true, true) => "-1".to_string(),
+        (false, false) => integer::format_factors(&factors),
+        (false, true) => format!("-1 * {}", integer::format_factors(&factors))
+    };
+    Ok(format!("{} = {}", int, product))
+}
+
+/// Finds the numbered results `_1`, `_2`, ... in order
+fn results<'a>(context: &'a Context) -> Vec<(u64, &'a Number)> {
+    let mut results: Vec<_> = context.variables.iter()
+        .filter_map(|(name, value)| Some((result_number(name)?, value)))
+        .collect();
+    results.sort_by_key(|&(i, _)| i);
+    results
+}
+/// Returns which result a variable like `_3` holds, if it's one of them
+fn result_number(name: &str) -> Option<u64> {
+    name.strip_prefix('_')?.parse().ok()
+}
+/// Checks if a variable holds an earlier result: `ans`, or one of `_1`, `_2`, ...
+pub fn is_result(name: &str) -> bool {
+    name == "ans" || result_number(name).is_some()
+}
+/// Keeps a result in the next numbered variable, and in `ans`
+fn record_result(context: &mut Context, result: Number) {
+    let next = results(context).last().map_or(1, |&(i, _)| i + 1);
+    context.variables.insert(format!("_{}", next), result.clone());
+    context.variables.insert("ans".to_string(), result);
+}
+
+/// Adds a line to the history, keeping to the "histsize" and "histdups" variables
+fn add_history(history: &mut DefaultHistory, context: &Context, line: &str) {
+    let added = history.set_max_len(setting(context, "histsize").unwrap_or(1000) as usize)
+        .and_then(|()| history.ignore_dups(false))
+        .and_then(|()| {
+            if setting(context, "histdups") == Some(0) {
+                // Move the line to the end instead of keeping an earlier copy of it
+                let entries: Vec<String> = history.iter()
+                    .filter(|entry| *entry != line)
+                    .cloned()
+                    .collect();
+                if entries.len() != history.len() {
+                    history.clear()?;
+                    for entry in entries {
+                        history.add_owned(entry)?;
+                    }
+                }
+            }
+            history.add(line).map(|_| ())
+        });
+    if let Err(err) = added {
+        eprintln!("Warning: Could not add the line to the history: {}", err);
+    }
+}
+
+/// Sets the special variables to what they are when the REPL starts
+fn set_defaults(variables: &mut HashMap<String, Number>) {
+    variables.insert("out".to_string(), Number::Decimal(BigDecimal::from(10)));
+    variables.insert("exact".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("digits".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("scale".to_string(), Number::Decimal(BigDecimal::from(Precision::default().scale)));
+    variables.insert("rounding".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("bits".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("signed".to_string(), Number::Decimal(BigDecimal::from(1)));
+    variables.insert("overflow".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("maxdigits".to_string(), Number::Decimal(BigDecimal::from(Limits::default().max_digits)));
+    variables.insert("maxapprox".to_string(), Number::Decimal(BigDecimal::from(Limits::default().max_approx_digits)));
+    variables.insert("maxsteps".to_string(), Number::Decimal(BigDecimal::from(Limits::default().max_steps)));
+    variables.insert("histsize".to_string(), Number::Decimal(BigDecimal::from(1000)));
+    variables.insert("histdups".to_string(), Number::Decimal(BigDecimal::from(0)));
+    variables.insert("histerrors".to_string(), Number::Decimal(BigDecimal::from(1)));
+}
+
+/// Prints an error, underlining the part of the input it's about.
+/// `indent` is how many columns the input line is shifted by (e.g. the prompt).
+pub fn print_error(indent: usize, err: &CalcError) {
+    if let Some(span) = err.span() {
+        let len = span.end.saturating_sub(span.start).max(1);
+        eprintln!("{}{}", " ".repeat(indent + span.start), "^".repeat(len));
+    }
+    eprintln!("Error: {}", err);
+}
+
+/// Runs a script line by line, printing the results.
+/// A `#` starts a comment, and a `\` at the end of a line continues the input on the next line.
+/// Returns false if any line failed, which unless `keep_going` is set is the last line run.
+fn run_script(name: &str, script: &str, context: &mut Context, keep_going: bool) -> bool {
+    let mut success = true;
+    let mut input = String::new();
+    // Where each line starts in the input, and its line number, to point errors back at the script
+    let mut lines = Vec::new();
+
+    let mut script_lines = script.lines().enumerate().peekable();
+    while let Some((i, line)) = script_lines.next() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+        let line = line.trim_end();
+        lines.push((input.chars().count(), i + 1));
+
+        if let Some(line) = line.strip_suffix('\\') {
+            input.push_str(line);
+            input.push(' ');
+            if script_lines.peek().is_some() {
+                continue;
+            }
+        } else {
+            input.push_str(line);
+        }
+
+        if !input.trim().is_empty() {
+            match calculate(&input, context) {
+                Ok(Outcome::Value(result)) => println!("{}", format(context, &result)),
+                Ok(Outcome::Assigned(..)) | Ok(Outcome::Defined(_)) => (),
+                Err(err) => {
+                    success = false;
+                    match err.span() {
+                        Some(span) => {
+                            let &(start, line) = lines.iter().rev().find(|&&(start, _)| start <= span.start).unwrap();
+                            eprintln!("{}:{}:{}: Error: {}", name, line, span.start - start + 1, err);
+                        },
+                        None => eprintln!("{}:{}: Error: {}", name, lines[0].1, err)
+                    }
+                    if !keep_going {
+                        return false;
+                    }
+                }
+            }
+        }
+        input.clear();
+        lines.clear();
+    }
+    success
+}
+
+/// Reads a special variable as a small whole number
+fn setting(context: &Context, name: &str) -> Option<u64> {
+    use num::ToPrimitive;
+    context.variables.get(name).and_then(|val| val.to_u64())
+}
+
+/// Calculates `input` with the settings in the special variables
+pub fn calculate(input: &str, context: &mut Context) -> Result<Outcome, CalcError> {
+    let tokens = parser::parse(input).map_err(CalcError::ParseError)?;
+    let expr = ast::parse(tokens.into_iter())?;
+    run_statements(context, &expr, |context| apply_settings(context, true))
+}
+
+/// Runs the statements in `expr` one at a time, calling `settings` before each,
+/// so that a line like `scale = 5; 1 / 3` already uses the new scale
+pub fn run_statements<F>(context: &mut Context, expr: &Expr, mut settings: F) -> Result<Outcome, CalcError>
+    where F: FnMut(&mut Context)
+{
+    let statements = match expr.kind {
+        ExprKind::Sequence(ref exprs) => &exprs[..],
+        _ => slice::from_ref(expr)
+    };
+    let (last, statements) = statements.split_last().unwrap();
+    for statement in statements {
+        settings(context);
+        calculator::run(context, statement)?;
+    }
+    settings(context);
+    calculator::run(context, last)
+}
+
+/// Sets the mode, precision, limits and width of `context` from the special variables.
+/// Unsupported values fall back to the defaults, with a warning if `warn` is set.
+pub fn apply_settings(context: &mut Context, warn: bool) {
+    context.mode = match setting(context, "exact") {
+        Some(0) | None => Mode::Decimal,
+        Some(_) => Mode::Rational
+    };
+    context.precision = Precision {
+        scale: setting(context, "scale").unwrap_or_else(|| Precision::default().scale),
+        rounding: match setting(context, "rounding") {
+            Some(0) => Rounding::Truncate,
+            Some(1) => Rounding::HalfUp,
+            Some(2) => Rounding::HalfEven,
+            Some(3) => Rounding::Floor,
+            Some(4) => Rounding::Ceiling,
+            _ => {
+                if warn {
+                    eprintln!("Warning: Unsupported \"rounding\" variable value");
+                }
+                Rounding::Truncate
+            }
+        }
+    };
+    context.limits = Limits {
+        max_digits: setting(context, "maxdigits").unwrap_or_else(|| Limits::default().max_digits),
+        max_approx_digits: setting(context, "maxapprox").unwrap_or_else(|| Limits::default().max_approx_digits),
+        max_steps: setting(context, "maxsteps").unwrap_or_else(|| Limits::default().max_steps)
+    };
+    context.width = match setting(context, "bits") {
+        Some(0) | None => None,
+        Some(bits @ 8) | Some(bits @ 16) | Some(bits @ 32) |
+        Some(bits @ 64) | Some(bits @ 128) | Some(bits @ 256) => Some(Width {
+            bits: bits as usize,
+            signed: setting(context, "signed") != Some(0),
+            strict: match setting(context, "overflow") {
+                Some(0) | None => false,
+                Some(_) => true
+            }
+        }),
+        Some(_) => {
+            if warn {
+                eprintln!("Warning: Unsupported \"bits\" variable value");
+            }
+            None
+        }
+    };
+}
+
+/// Formats a result in the base and number of decimal places the special variables ask for
+pub fn format(context: &Context, result: &Number) -> String {
+    try_format(context, result).unwrap_or_else(|| {
+        eprintln!("Warning: Unsupported \"out\" variable value");
+        result.to_string()
+    })
+}
+
+/// Like `format`, but returns `None` instead of warning if the "out" variable is unsupported
+pub fn try_format(context: &Context, result: &Number) -> Option<String> {
+    use num::bigint::ToBigInt;
+    // Show negatives in other bases as their two's complement bit pattern
+    let int = || {
+        let int = result.to_bigint().unwrap();
+        match context.width {
+            Some(width) => width.to_unsigned(&int),
+            None => int
+        }
+    };
+    match setting(context, "out") {
+        Some(2)  => Some(format!("{:b}", int())),
+        Some(8)  => Some(format!("{:o}", int())),
+        Some(10) => Some(match setting(context, "digits") {
+            Some(0) | None => result.to_string(),
+            Some(digits) => result.to_decimal_places(digits, context.precision.rounding).to_string()
+        }),
+        Some(16) => Some(format!("{:X}", int())),
+        _  => None
     }
-    None
 }
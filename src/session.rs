@@ -0,0 +1,115 @@
+use bigdecimal::BigDecimal;
+use {calculate, is_result};
+use num::{BigInt, BigRational};
+use simple_math_lib::ast::{Expr, ExprKind};
+use simple_math_lib::calculator::{Context, Function};
+use simple_math_lib::number::Number;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Where the REPL keeps its variables and functions between runs,
+/// `$XDG_DATA_HOME/simple-math/session` or `~/.local/share/simple-math/session`
+pub fn default_path() -> Option<PathBuf> {
+    xdg_path("XDG_DATA_HOME", ".local/share", "session")
+}
+/// Where the REPL keeps the lines entered in earlier runs,
+/// `$XDG_STATE_HOME/simple-math/history` or `~/.local/state/simple-math/history`
+pub fn history_path() -> Option<PathBuf> {
+    xdg_path("XDG_STATE_HOME", ".local/state", "history")
+}
+fn xdg_path(var: &str, fallback: &str, file: &str) -> Option<PathBuf> {
+    let dir = match env::var_os(var) {
+        Some(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(fallback)
+    };
+    Some(dir.join("simple-math").join(file))
+}
+
+/// Writes all variables and functions to `path` as source code, one per line.
+/// The earlier results are left out, since they'd pile up over every run that saves them.
+pub fn save(path: &Path, context: &Context) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    writeln!(file, "# simple-math session")?;
+
+    let mut variables: Vec<_> = context.variables.iter().filter(|&(name, _)| !is_result(name)).collect();
+    variables.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, value) in variables {
+        writeln!(file, "{} = {}", name, value)?;
+    }
+
+    let mut functions: Vec<_> = context.functions.iter().collect();
+    functions.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, function) in functions {
+        writeln!(file, "{}", definition(name, function))?;
+    }
+    file.flush()
+}
+
+/// Turns a function back into the expression that defines it, which displays as source code
+pub fn definition(name: &str, function: &Function) -> Expr {
+    let define = ExprKind::Define(name.to_string(), function.params.clone(), Box::new(function.body.clone()));
+    Expr::new(Default::default(), define)
+}
+
+/// Runs a file written by `save` (or by hand) line by line, without printing the results.
+/// Variables set to a plain number or fraction are read as they are, so they're
+/// restored exactly no matter which mode the calculator is in.
+pub fn load(path: &Path, context: &mut Context) -> Result<(), String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("Could not read {}: {}", path.display(), err))?;
+
+    for (i, line) in source.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some((name, value)) = parse_variable(line) {
+            context.variables.insert(name, value);
+            continue;
+        }
+        if let Err(err) = calculate(line, context) {
+            return Err(match err.span() {
+                Some(span) => format!("{}:{}:{}: {}", path.display(), i + 1, span.start + 1, err),
+                None => format!("{}:{}: {}", path.display(), i + 1, err)
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Reads a line like `x = -1.5` or `x = 1/3`
+fn parse_variable(line: &str) -> Option<(String, Number)> {
+    let mut parts = line.splitn(2, '=');
+    let name = parts.next()?.trim();
+    let value = parts.next()?.trim();
+
+    match name.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return None
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    let value = match value.find('/') {
+        Some(slash) => {
+            let numer: BigInt = value[..slash].trim().parse().ok()?;
+            let denom: BigInt = value[slash + 1..].trim().parse().ok()?;
+            if denom == BigInt::from(0) {
+                return None;
+            }
+            Number::Rational(BigRational::new(numer, denom))
+        },
+        None => Number::Decimal(value.parse::<BigDecimal>().ok()?)
+    };
+    Some((name.to_string(), value))
+}